@@ -0,0 +1,96 @@
+//! Throughput of a room's hub with thousands of subscribers.
+//!
+//! Run with `cargo bench -p backend --bench fanout`.
+
+use std::time::{Duration, Instant};
+
+use backend::hub::Hub;
+use backend::proto::ChatMessage;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+
+/// Messages published per measured iteration.
+const MESSAGES: usize = 100;
+
+fn message() -> ChatMessage {
+    ChatMessage {
+        from: String::from("bench"),
+        msg: String::from("hello everyone"),
+        room_id: String::from("general"),
+        ..Default::default()
+    }
+}
+
+/// Publishes `MESSAGES` messages and returns how long it took until every
+/// subscriber had received all of them.
+async fn deliver(subscribers: usize) -> Duration {
+    // Room for every message, so nobody lags and is disconnected.
+    let hub = Hub::new(MESSAGES);
+    let readers: Vec<_> = (0..subscribers)
+        .map(|_| {
+            let mut subscription = hub.subscribe();
+            tokio::spawn(async move {
+                let mut received = 0;
+                while received < MESSAGES {
+                    match subscription.next().await {
+                        Some(Ok(_)) => received += 1,
+                        _ => break,
+                    }
+                }
+                received
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        hub.publish(message());
+    }
+    for reader in readers {
+        assert_eq!(reader.await.unwrap(), MESSAGES);
+    }
+    start.elapsed()
+}
+
+fn fanout(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fanout");
+    group.sample_size(10);
+    for subscribers in [1_000, 5_000, 10_000] {
+        group.throughput(Throughput::Elements((subscribers * MESSAGES) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(subscribers),
+            &subscribers,
+            |b, &subscribers| {
+                b.to_async(&runtime).iter_custom(|iters| async move {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        total += deliver(subscribers).await;
+                    }
+                    total
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Publishing must not slow down when subscribers stop reading; they lag and
+/// get disconnected instead.
+fn stalled_subscribers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_with_stalled_subscribers");
+    for subscribers in [0, 1_000, 10_000] {
+        let hub = Hub::new(MESSAGES);
+        let stalled: Vec<_> = (0..subscribers).map(|_| hub.subscribe()).collect();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(subscribers),
+            &subscribers,
+            |b, _| b.iter(|| hub.publish(message())),
+        );
+        drop(stalled);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout, stalled_subscribers);
+criterion_main!(benches);
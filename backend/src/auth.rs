@@ -0,0 +1,153 @@
+//! Session tokens handed out by `Join`.
+//!
+//! Clients send the token back as `authorization: Bearer <token>` metadata.
+//! [`interceptor`] resolves it to the user it was minted for, so handlers
+//! never have to trust a user name supplied in the request body.
+//!
+//! Names can be taken again once their user leaves, so anything that must
+//! stay with one person, such as access to a direct conversation, goes by
+//! the user's id instead. [`user_id`] issues a new one at every `Join`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::shutdown::Shutdown;
+
+/// The user a request was authenticated as, stored in the request
+/// extensions by [`interceptor`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub id: String,
+    pub name: String,
+}
+
+/// Begins once the session a request was authenticated with is revoked,
+/// stored in the request extensions by [`interceptor`].
+#[derive(Clone)]
+pub struct Revocation(pub Shutdown);
+
+struct Session {
+    user: AuthenticatedUser,
+    revoked: Shutdown,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    // By token. A std lock because interceptors are synchronous.
+    tokens: RwLock<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Creates a new session for `user` and returns its token.
+    pub fn mint(&self, user: &AuthenticatedUser) -> String {
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        self.tokens
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(
+                token.clone(),
+                Session {
+                    user: user.clone(),
+                    revoked: Shutdown::new(),
+                },
+            );
+        token
+    }
+
+    pub fn user(&self, token: &str) -> Option<AuthenticatedUser> {
+        self.session(token).map(|(user, _)| user)
+    }
+
+    fn session(&self, token: &str) -> Option<(AuthenticatedUser, Shutdown)> {
+        self.tokens
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(token)
+            .map(|session| (session.user.clone(), session.revoked.clone()))
+    }
+
+    /// Returns the id of every user with at least one session.
+    pub fn user_ids(&self) -> Vec<String> {
+        let tokens = self
+            .tokens
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut users: Vec<_> = tokens
+            .values()
+            .map(|session| session.user.id.clone())
+            .collect();
+        users.sort();
+        users.dedup();
+        users
+    }
+
+    /// Invalidates every session of the user with id `id` and ends the
+    /// streams opened with them.
+    pub fn revoke_user(&self, id: &str) {
+        self.tokens
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|_, session| {
+                let keep = session.user.id != id;
+                if !keep {
+                    session.revoked.begin();
+                }
+                keep
+            });
+    }
+}
+
+/// Attaches an [`AuthenticatedUser`] to requests carrying a valid bearer
+/// token. Anything else passes through without one, so `Join` works with a
+/// stale token and handlers decide for themselves via [`authenticated`].
+pub fn interceptor(sessions: Arc<Sessions>) -> impl Interceptor + Clone {
+    move |mut request: Request<()>| {
+        let session = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer_token)
+            .and_then(|token| sessions.session(token));
+        if let Some((user, revoked)) = session {
+            tracing::Span::current().record("user", user.name.as_str());
+            request.extensions_mut().insert(user);
+            request.extensions_mut().insert(Revocation(revoked));
+        }
+        Ok(request)
+    }
+}
+
+/// Returns a new user id. Unlike names, ids are never given out twice.
+pub fn user_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// The token in an `authorization` value of the form `Bearer <token>`.
+pub fn bearer_token(value: &str) -> Option<&str> {
+    value.strip_prefix("Bearer ")
+}
+
+/// Returns the user `request` was authenticated as.
+pub fn authenticated<T>(request: &Request<T>) -> Result<AuthenticatedUser, Status> {
+    request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("Missing or expired session, log in again."))
+}
+
+/// Returns what ends the streams of the session `request` was authenticated
+/// with, if it was.
+pub fn revocation<T>(request: &Request<T>) -> Option<Shutdown> {
+    request
+        .extensions()
+        .get::<Revocation>()
+        .map(|revocation| revocation.0.clone())
+}
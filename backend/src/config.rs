@@ -0,0 +1,208 @@
+//! Settings for the chat backend.
+//!
+//! Each setting is taken from the first of these that has it: a command line
+//! flag, a `CHAT_*` environment variable, the TOML file named by `--config`,
+//! and finally its default. Run with `--help` for the full list.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use chat_common::config::{quota, read_pem, read_toml};
+
+use crate::ratelimit::Quota;
+
+pub use chat_common::config::ConfigError;
+
+/// One layer of settings. Unset fields fall through to the next layer.
+#[derive(Debug, Default, Parser, Deserialize)]
+#[command(version, about = "gRPC chat backend")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// TOML file to read settings from, with the same names as the flags
+    /// written in snake_case.
+    #[arg(long, env = "CHAT_CONFIG")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Address to serve gRPC on [default: [::1]:50051].
+    #[arg(long, env = "CHAT_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// SQLite database to keep history in across restarts. History is only
+    /// kept in memory when unset.
+    #[arg(long, env = "CHAT_DB")]
+    pub db: Option<PathBuf>,
+
+    /// PEM certificate chain to serve TLS with.
+    #[arg(long, env = "CHAT_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `tls_cert`.
+    #[arg(long, env = "CHAT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA certificates to verify client certificates against. Clients
+    /// without a valid certificate are refused when set.
+    #[arg(long, env = "CHAT_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Messages returned by `GetHistory` when the request doesn't set a
+    /// limit [default: 100].
+    #[arg(long, env = "CHAT_HISTORY_LIMIT")]
+    pub history_limit: Option<usize>,
+
+    /// Most messages `GetHistory` returns in one page [default: 500].
+    #[arg(long, env = "CHAT_MAX_HISTORY_LIMIT")]
+    pub max_history_limit: Option<usize>,
+
+    /// Results returned by `SearchMessages` when the request doesn't set a
+    /// limit [default: 20].
+    #[arg(long, env = "CHAT_SEARCH_LIMIT")]
+    pub search_limit: Option<usize>,
+
+    /// Most results `SearchMessages` returns in one page [default: 100].
+    #[arg(long, env = "CHAT_MAX_SEARCH_LIMIT")]
+    pub max_search_limit: Option<usize>,
+
+    /// Seconds a user stays logged in after their last stream closes
+    /// [default: 30].
+    #[arg(long, env = "CHAT_PRESENCE_GRACE")]
+    pub presence_grace: Option<u64>,
+
+    /// Messages each user may send per second, on average [default: 1].
+    #[arg(long, env = "CHAT_SEND_RATE")]
+    pub send_rate: Option<f64>,
+
+    /// Messages each user may send in a burst before `send_rate` applies
+    /// [default: 5].
+    #[arg(long, env = "CHAT_SEND_BURST")]
+    pub send_burst: Option<u32>,
+
+    /// Seconds to wait on shutdown for calls in flight to finish before
+    /// closing their connections [default: 10].
+    #[arg(long, env = "CHAT_DRAIN_TIMEOUT")]
+    pub drain_timeout: Option<u64>,
+
+    /// Log filter, e.g. `info` or `backend=debug` [default: info].
+    #[arg(long, env = "CHAT_LOG")]
+    pub log_level: Option<String>,
+
+    /// OpenTelemetry collector to export spans to over OTLP/gRPC, e.g.
+    /// `http://localhost:4317`. Spans aren't exported when unset.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Bounds on what a single request may ask for.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub history: usize,
+    pub max_history: usize,
+    pub search: usize,
+    pub max_search: usize,
+    pub presence_grace: Duration,
+}
+
+/// Certificate and key files to serve TLS with.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Reads the certificates for `Server::tls_config`. With a client CA,
+    /// clients must present a certificate it issued.
+    pub fn server_config(&self) -> Result<ServerTlsConfig, ConfigError> {
+        let identity = Identity::from_pem(read_pem(&self.cert)?, read_pem(&self.key)?);
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(ca) = &self.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(read_pem(ca)?));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub db: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    pub limits: Limits,
+    pub send_quota: Quota,
+    pub drain_timeout: Duration,
+    pub log_level: String,
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    /// Loads the settings from the command line, the environment and the
+    /// config file. Exits with a usage message on invalid flags.
+    pub fn load() -> Result<Self, ConfigError> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => read_toml(path)?,
+            None => Args::default(),
+        };
+        Self::from_layers(args, file)
+    }
+
+    /// Combines two layers, `args` taking precedence over `file`.
+    pub fn from_layers(args: Args, file: Args) -> Result<Self, ConfigError> {
+        let tls = match (
+            args.tls_cert.or(file.tls_cert),
+            args.tls_key.or(file.tls_key),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert,
+                key,
+                client_ca: args.tls_client_ca.or(file.tls_client_ca),
+            }),
+            (None, None) => None,
+            (Some(_), None) => return Err(ConfigError::Incomplete("tls_cert", "tls_key")),
+            (None, Some(_)) => return Err(ConfigError::Incomplete("tls_key", "tls_cert")),
+        };
+        let send_quota = quota(
+            ("send_rate", args.send_rate.or(file.send_rate), 1.0),
+            ("send_burst", args.send_burst.or(file.send_burst), 5),
+        )?;
+        Ok(Self {
+            listen: args
+                .listen
+                .or(file.listen)
+                .unwrap_or_else(|| "[::1]:50051".parse().unwrap()),
+            db: args.db.or(file.db),
+            tls,
+            limits: Limits {
+                history: args.history_limit.or(file.history_limit).unwrap_or(100),
+                max_history: args
+                    .max_history_limit
+                    .or(file.max_history_limit)
+                    .unwrap_or(500),
+                search: args.search_limit.or(file.search_limit).unwrap_or(20),
+                max_search: args
+                    .max_search_limit
+                    .or(file.max_search_limit)
+                    .unwrap_or(100),
+                presence_grace: Duration::from_secs(
+                    args.presence_grace.or(file.presence_grace).unwrap_or(30),
+                ),
+            },
+            send_quota,
+            drain_timeout: Duration::from_secs(
+                args.drain_timeout.or(file.drain_timeout).unwrap_or(10),
+            ),
+            log_level: args
+                .log_level
+                .or(file.log_level)
+                .unwrap_or_else(|| String::from("info")),
+            otlp_endpoint: args.otlp_endpoint.or(file.otlp_endpoint),
+        })
+    }
+}
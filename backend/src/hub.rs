@@ -0,0 +1,82 @@
+//! Per-room fan-out of chat traffic to subscribed streams.
+//!
+//! Publishing never waits on subscribers: every subscriber reads from its own
+//! position in a shared ring. A subscriber that falls more than `capacity`
+//! items behind receives a `RESOURCE_EXHAUSTED` status and its stream ends, so
+//! it can reconnect and resume from the last message it saw.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+/// Items a subscriber may fall behind by before it is disconnected.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+pub struct Hub<T> {
+    sender: broadcast::Sender<T>,
+}
+
+impl<T: Clone + Send + 'static> Hub<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Queues `item` for every current subscriber and returns how many there
+    /// were.
+    pub fn publish(&self, item: T) -> usize {
+        self.sender.send(item).unwrap_or(0)
+    }
+
+    /// Returns a stream of everything published from now on.
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription {
+            inner: BroadcastStream::new(self.sender.subscribe()),
+            lagged: false,
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for Hub<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// A subscriber's view of a [`Hub`]. Ends with an error once the subscriber
+/// lags behind.
+pub struct Subscription<T> {
+    inner: BroadcastStream<T>,
+    lagged: bool,
+}
+
+impl<T: Clone + Send + 'static> Stream for Subscription<T> {
+    type Item = tonic::Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.lagged {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Err(BroadcastStreamRecvError::Lagged(missed)))) => {
+                self.lagged = true;
+                Poll::Ready(Some(Err(tonic::Status::resource_exhausted(format!(
+                    "Lagged behind by {} messages, resume from the last one received.",
+                    missed
+                )))))
+            }
+            Poll::Ready(Some(Ok(item))) => Poll::Ready(Some(Ok(item))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
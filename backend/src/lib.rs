@@ -1,3 +1,11 @@
-pub mod proto {
-    tonic::include_proto!("chat");
-}
+pub use chat_common::shutdown;
+pub use chat_proto as proto;
+pub mod auth;
+pub mod config;
+pub mod hub;
+pub mod metrics;
+pub mod presence;
+pub mod ratelimit;
+pub mod search;
+pub mod storage;
+pub mod telemetry;
@@ -1,18 +1,538 @@
 use futures::lock::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tonic::service::Routes;
 use tonic::transport::Server;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
 
 use backend::proto::chat_service_server::ChatService;
-use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
 
-use backend::proto::chat_service_server::ChatServiceServer;
+use backend::auth::{self, AuthenticatedUser, Sessions};
+use backend::config::{Config, Limits};
+use backend::hub::Hub;
+use backend::metrics::{Metrics, MetricsLayer, Observed};
+use backend::presence::{Presence, Tracked};
+use backend::proto::chat_event::Event;
+use backend::proto::chat_service_server::{ChatServiceServer, SERVICE_NAME};
+use backend::proto::presence_event::Kind;
+use backend::proto::{direct_participants, direct_room_id};
+use backend::ratelimit::RateLimitLayer;
+use backend::search::{Filter, SearchIndex};
+use backend::shutdown::{self, Shutdown};
+use backend::storage::{MemoryStore, MessageStore, Page, SqliteStore, StorageError};
+use backend::telemetry;
+use tower_http::trace::{DefaultOnFailure, TraceLayer};
+use tracing::Level;
+
+/// Room every user is placed in when they join, and the room used for
+/// requests that don't name one.
+const DEFAULT_ROOM_ID: &str = "general";
+
+/// Recent messages kept per room so a client that lost its stream can resume
+/// where it left off.
+const REPLAY_BUFFER: usize = 1024;
+
+/// How long a `SetTyping` lasts unless the client renews it.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest message accepted, in bytes.
+const MAX_MESSAGE_LEN: usize = 1000;
+
+/// Longest emoji accepted as a reaction, in characters. Enough for flags and
+/// joined sequences.
+const MAX_EMOJI_CHARS: usize = 8;
+
+/// How often storage is checked to report the service's health.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+type EventStream = Pin<Box<dyn Stream<Item = tonic::Result<backend::proto::ChatEvent>> + Send>>;
 
 #[derive(Default)]
+struct RoomState {
+    room: backend::proto::Room,
+    members: HashSet<String>,
+    hub: Hub<backend::proto::ChatEvent>,
+    /// The last `REPLAY_BUFFER` events, oldest first.
+    recent: VecDeque<backend::proto::ChatEvent>,
+    /// Every event in the room with a higher seq than this is in `recent`.
+    replay_floor: u64,
+    /// Users currently typing, with when their latest `SetTyping` runs out.
+    typing: HashMap<String, Instant>,
+}
+
+impl RoomState {
+    /// Fills the replay buffer from the stored messages most recently posted
+    /// or changed, so clients can resume across a restart.
+    fn seed(&mut self, messages: Vec<backend::proto::ChatMessage>) {
+        // Every message left out was last posted or changed before any of
+        // these.
+        if messages.len() == REPLAY_BUFFER {
+            let oldest = messages.iter().map(|msg| msg.id.max(msg.revision)).min();
+            self.replay_floor = oldest.unwrap_or(0).saturating_sub(1);
+        }
+        // A changed message is replayed as posted and then changed, so a
+        // client that saw the original still learns about the change.
+        let mut events: Vec<_> = messages
+            .iter()
+            .filter(|msg| msg.revision != 0)
+            .cloned()
+            .map(changed)
+            .collect();
+        events.extend(messages.into_iter().map(new_message));
+        events.sort_by_key(|event| event.seq);
+        let excess = events.len().saturating_sub(REPLAY_BUFFER);
+        if let Some(dropped) = events.drain(..excess).next_back() {
+            self.replay_floor = self.replay_floor.max(dropped.seq);
+        }
+        self.recent = events.into();
+    }
+
+    fn remember(&mut self, event: backend::proto::ChatEvent) {
+        while self.recent.len() >= REPLAY_BUFFER {
+            if let Some(evicted) = self.recent.pop_front() {
+                self.replay_floor = evicted.seq;
+            }
+        }
+        self.recent.push_back(event);
+    }
+
+    /// Returns the events after `after_id`, or `None` if some of them have
+    /// already been evicted.
+    fn replay(&self, after_id: u64) -> Option<Vec<backend::proto::ChatEvent>> {
+        if after_id < self.replay_floor {
+            return None;
+        }
+        Some(
+            self.recent
+                .iter()
+                .filter(|event| event.seq > after_id)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Whether `user` may post or react in the room. Access to direct
+    /// conversations is checked up front with `check_access`.
+    fn may_post(&self, user: &str) -> bool {
+        direct_participants(&self.room.id).is_some() || self.members.contains(user)
+    }
+
+    /// Marks `user` as typing for another `TYPING_TIMEOUT`. Returns whether
+    /// they just started, which is the only change subscribers hear about.
+    fn start_typing(&mut self, user: &str) -> bool {
+        let until = Instant::now() + TYPING_TIMEOUT;
+        let started = self.typing.insert(user.to_string(), until).is_none();
+        if started {
+            self.publish_typing(user, true);
+        }
+        started
+    }
+
+    /// Clears `user`'s typing state.
+    fn stop_typing(&mut self, user: &str) {
+        if self.typing.remove(user).is_some() {
+            self.publish_typing(user, false);
+        }
+    }
+
+    /// Typing updates go to current subscribers only, they aren't worth
+    /// replaying.
+    fn publish_typing(&self, user: &str, typing: bool) {
+        self.hub.publish(backend::proto::ChatEvent {
+            seq: 0,
+            event: Some(Event::Typing(backend::proto::Typing {
+                room_id: self.room.id.clone(),
+                user: user.to_string(),
+                typing,
+            })),
+        });
+    }
+
+    /// Replaces every buffered copy of a deleted message with its tombstone,
+    /// so replays don't hand out the deleted text.
+    fn forget(&mut self, tombstone: &backend::proto::ChatMessage) {
+        for event in &mut self.recent {
+            if let Some(Event::NewMessage(msg) | Event::Edited(msg)) = &mut event.event {
+                if msg.id == tombstone.id {
+                    *msg = tombstone.clone();
+                }
+            }
+        }
+    }
+}
+
+fn new_message(msg: backend::proto::ChatMessage) -> backend::proto::ChatEvent {
+    backend::proto::ChatEvent {
+        seq: msg.id,
+        event: Some(Event::NewMessage(msg)),
+    }
+}
+
+/// The event replaying the latest change to `msg`. Every change carries the
+/// whole message, so a reaction change replayed as an edit reads the same.
+fn changed(msg: backend::proto::ChatMessage) -> backend::proto::ChatEvent {
+    backend::proto::ChatEvent {
+        seq: msg.revision,
+        event: Some(if msg.deleted {
+            Event::Deleted(msg)
+        } else {
+            Event::Edited(msg)
+        }),
+    }
+}
+
+type Rooms = Arc<Mutex<HashMap<String, RoomState>>>;
+
 struct Chat {
-    user_list: Mutex<backend::proto::UserList>,
-    messages: Mutex<
-        Vec<Arc<Mutex<tokio::sync::mpsc::Sender<tonic::Result<backend::proto::ChatMessage>>>>>,
-    >,
+    sessions: Arc<Sessions>,
+    presence: Arc<Presence>,
+    rooms: Rooms,
+    storage: Arc<dyn MessageStore>,
+    /// Id of the most recently accepted message.
+    last_id: AtomicU64,
+    /// Only updated under the rooms lock, after the change is stored.
+    search: std::sync::Mutex<SearchIndex>,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    shutdown: Shutdown,
+}
+
+impl Chat {
+    /// Loads the saved rooms from `storage`, creating the default room on
+    /// first start.
+    async fn new(
+        storage: impl MessageStore,
+        limits: Limits,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, StorageError> {
+        let mut saved = storage.rooms().await?;
+        if !saved.iter().any(|room| room.id == DEFAULT_ROOM_ID) {
+            let general = backend::proto::Room {
+                id: DEFAULT_ROOM_ID.into(),
+                name: String::from("General"),
+                ..Default::default()
+            };
+            storage.save_room(general.clone()).await?;
+            saved.push(general);
+        }
+
+        let mut rooms = HashMap::new();
+        for room in saved {
+            let mut state = RoomState {
+                room,
+                ..Default::default()
+            };
+            state.seed(storage.recent(&state.room.id, REPLAY_BUFFER).await?);
+            rooms.insert(state.room.id.clone(), state);
+        }
+        let rooms = Arc::new(Mutex::new(rooms));
+
+        let sessions = Sessions::new();
+        let presence = Presence::new(limits.presence_grace);
+        tokio::spawn(forget_departed(
+            Arc::clone(&presence),
+            presence.subscribe(),
+            Arc::clone(&sessions),
+            Arc::clone(&rooms),
+        ));
+
+        let last_id = AtomicU64::new(storage.last_id().await?);
+
+        let mut search = SearchIndex::new();
+        for msg in storage.messages().await? {
+            search.insert(msg);
+        }
+
+        Ok(Self {
+            sessions,
+            presence,
+            rooms,
+            storage: Arc::new(storage),
+            last_id,
+            search: std::sync::Mutex::new(search),
+            limits,
+            metrics,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Returns the state of `room_id`. Direct conversations aren't loaded at
+    /// startup, their state is created from storage on first use. That takes
+    /// both users still being logged in, or the conversation having
+    /// messages, so made-up ids don't create rooms.
+    #[tracing::instrument(level = "debug", skip(self, rooms))]
+    async fn room<'a>(
+        &self,
+        rooms: &'a mut HashMap<String, RoomState>,
+        room_id: &str,
+    ) -> tonic::Result<&'a mut RoomState> {
+        if let (false, Some((a, b))) = (rooms.contains_key(room_id), direct_participants(room_id)) {
+            let recent = self.storage.recent(room_id, REPLAY_BUFFER).await?;
+            if recent.is_empty() && !(self.presence.contains_id(a) && self.presence.contains_id(b))
+            {
+                return Err(tonic::Status::not_found("Room does not exist."));
+            }
+            evict_idle(rooms);
+            let mut state = RoomState {
+                room: backend::proto::Room {
+                    id: room_id.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            state.seed(recent);
+            rooms.insert(room_id.to_string(), state);
+        }
+        rooms
+            .get_mut(room_id)
+            .ok_or_else(|| tonic::Status::not_found("Room does not exist."))
+    }
+
+    /// Assigns `msg` its id and time, stores it and sends it to everyone
+    /// subscribed to `room`.
+    #[tracing::instrument(skip_all, fields(room = %room.room.id, id))]
+    async fn publish(
+        &self,
+        room: &mut RoomState,
+        mut msg: backend::proto::ChatMessage,
+    ) -> tonic::Result<()> {
+        if msg.parent_id != 0 {
+            let parent = self.storage.message(msg.parent_id).await?;
+            if !parent.is_some_and(|parent| parent.room_id == msg.room_id && parent.parent_id == 0)
+            {
+                return Err(tonic::Status::invalid_argument(
+                    "Replies must be to a top-level message in the same room.",
+                ));
+            }
+        }
+        // Assigned under the rooms lock so subscribers see messages in id order.
+        msg.id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        msg.sent_at = Some(SystemTime::now().into());
+        tracing::Span::current().record("id", msg.id);
+        self.storage.append(msg.clone()).await?;
+        self.metrics.messages.inc();
+        self.index(msg.clone());
+        room.stop_typing(&msg.from);
+        let event = new_message(msg);
+        room.remember(event.clone());
+        room.hub.publish(event);
+        Ok(())
+    }
+
+    fn index(&self, msg: backend::proto::ChatMessage) {
+        self.search
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(msg);
+    }
+
+    /// Applies `change` to the stored message `id` on behalf of `user` and
+    /// tells everyone subscribed to its room with a `kind` event. `allowed`
+    /// decides whether the user may change the message; `change` returns
+    /// whether it actually did.
+    #[tracing::instrument(skip(self, allowed, change, kind))]
+    async fn change(
+        &self,
+        id: u64,
+        user: &AuthenticatedUser,
+        allowed: impl FnOnce(&backend::proto::ChatMessage, &RoomState) -> bool,
+        change: impl FnOnce(&mut backend::proto::ChatMessage) -> bool,
+        kind: fn(backend::proto::ChatMessage) -> Event,
+    ) -> tonic::Result<()> {
+        // Load under the rooms lock so concurrent changes apply in seq order.
+        let mut rooms = self.rooms.lock().await;
+        let mut msg = self
+            .storage
+            .message(id)
+            .await?
+            .filter(|msg| check_access(&msg.room_id, &user.id).is_ok())
+            .ok_or_else(|| tonic::Status::not_found("Message does not exist."))?;
+        if msg.deleted {
+            return Err(tonic::Status::failed_precondition(
+                "Message has been deleted.",
+            ));
+        }
+        let room = self.room(&mut rooms, &msg.room_id).await?;
+        if !allowed(&msg, room) {
+            return Err(tonic::Status::permission_denied(
+                "Not allowed to change this message.",
+            ));
+        }
+
+        if !change(&mut msg) {
+            return Ok(());
+        }
+        msg.revision = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.storage.update(msg.clone()).await?;
+        self.index(msg.clone());
+        if msg.deleted {
+            room.forget(&msg);
+        }
+        let event = backend::proto::ChatEvent {
+            seq: msg.revision,
+            event: Some(kind(msg)),
+        };
+        room.remember(event.clone());
+        room.hub.publish(event);
+        Ok(())
+    }
+}
+
+/// Ends the sessions of users who leave or time out and removes them from
+/// every room, so the name can be reused without inheriting memberships.
+/// Their direct conversations are dropped from memory once idle.
+async fn forget_departed(
+    presence: Arc<Presence>,
+    mut events: broadcast::Receiver<backend::proto::PresenceEvent>,
+    sessions: Arc<Sessions>,
+    rooms: Rooms,
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) if event.kind() == Kind::Left => {
+                let Some(user) = event.user else { continue };
+                sessions.revoke_user(&user.id);
+                let mut rooms = rooms.lock().await;
+                for room in rooms.values_mut() {
+                    room.members.remove(&user.name);
+                }
+                evict_idle(&mut rooms);
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                // Some departures were missed; fall back to the full list.
+                let users = presence.users();
+                let present: HashSet<_> = users.iter().map(|u| u.name.as_str()).collect();
+                let present_ids: HashSet<_> = users.iter().map(|u| u.id.as_str()).collect();
+                for id in sessions.user_ids() {
+                    if !present_ids.contains(id.as_str()) {
+                        sessions.revoke_user(&id);
+                    }
+                }
+                let mut rooms = rooms.lock().await;
+                for room in rooms.values_mut() {
+                    room.members
+                        .retain(|member| present.contains(member.as_str()));
+                }
+                evict_idle(&mut rooms);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Clears `user`'s typing state in `room_id` once it runs out without a
+/// renewal. Returns early if it is cleared some other way.
+async fn expire_typing(rooms: Rooms, room_id: String, user: String) {
+    let mut until = Instant::now() + TYPING_TIMEOUT;
+    loop {
+        tokio::time::sleep_until(until).await;
+        let mut rooms = rooms.lock().await;
+        let Some(room) = rooms.get_mut(&room_id) else {
+            return;
+        };
+        match room.typing.get(&user) {
+            Some(&renewed) if renewed > Instant::now() => until = renewed,
+            Some(_) => return room.stop_typing(&user),
+            None => return,
+        }
+    }
+}
+
+/// Reports the chat service, and the server as a whole, as serving while
+/// `storage` answers and as not serving while it fails.
+async fn report_health(storage: Arc<dyn MessageStore>, reporter: HealthReporter) {
+    let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    let mut serving = None;
+    loop {
+        interval.tick().await;
+        let status = match storage.ping().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(e) => {
+                tracing::warn!("Storage check failed: {}", e);
+                ServingStatus::NotServing
+            }
+        };
+        if serving != Some(status) {
+            tracing::info!("Health: {}", status);
+            reporter.set_service_status("", status).await;
+            reporter.set_service_status(SERVICE_NAME, status).await;
+            serving = Some(status);
+        }
+    }
+}
+
+/// Derives a URL-friendly room id from its display name, e.g.
+/// `"Rust Talk!"` becomes `"rust-talk"`.
+fn room_id_from_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Drops the state of direct conversations nobody is subscribed to. It is
+/// created from storage again on next use.
+fn evict_idle(rooms: &mut HashMap<String, RoomState>) {
+    rooms.retain(|room_id, state| {
+        direct_participants(room_id).is_none() || state.hub.subscribers() > 0
+    });
+}
+
+/// Fails unless the user with id `user_id` may read `room_id`. Every room is
+/// public except direct conversations, which only their two participants can
+/// see.
+fn check_access(room_id: &str, user_id: &str) -> tonic::Result<()> {
+    match direct_participants(room_id) {
+        Some((a, b)) if user_id != a && user_id != b => Err(tonic::Status::permission_denied(
+            "Direct messages are only visible to their participants.",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Trims `text` and fails if that leaves it empty or too long to send.
+fn check_text(text: &str) -> tonic::Result<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(tonic::Status::invalid_argument("Message cannot be empty."));
+    }
+    if text.len() > MAX_MESSAGE_LEN {
+        return Err(tonic::Status::invalid_argument(format!(
+            "Message too long (max {} characters).",
+            MAX_MESSAGE_LEN
+        )));
+    }
+    Ok(text.to_string())
+}
+
+fn check_emoji(emoji: String) -> tonic::Result<String> {
+    let emoji = emoji.trim();
+    if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_CHARS {
+        return Err(tonic::Status::invalid_argument(
+            "Reaction must be a single emoji.",
+        ));
+    }
+    Ok(emoji.to_string())
+}
+
+/// Requests that leave `room_id` empty address the default room.
+fn room_or_default(room_id: String) -> String {
+    if room_id.is_empty() {
+        String::from(DEFAULT_ROOM_ID)
+    } else {
+        room_id
+    }
 }
 
 #[tonic::async_trait]
@@ -21,102 +541,668 @@ impl ChatService for Chat {
         &self,
         request: tonic::Request<backend::proto::User>,
     ) -> tonic::Result<tonic::Response<backend::proto::JoinResponse>> {
-        println!("[join] Method called");
-        let new_user = request.into_inner();
-        println!("[join] User: {:?}", new_user);
-        let mut user_list = self.user_list.lock().await;
-        let mut response = None;
-
-        if user_list
-            .users
-            .iter()
-            .find(|existing_user| existing_user.name == new_user.name)
-            .is_none()
-        {
-            user_list.users.push(new_user);
-            let _ = response.insert(tonic::Response::new(backend::proto::JoinResponse {
+        // A still-valid session for the same name may log in again, e.g.
+        // after a page reload, and keeps its id.
+        let current = auth::authenticated(&request).ok();
+        let mut new_user = request.into_inner();
+        let name = new_user.name.clone();
+        let rejoining = current.filter(|current| current.name == name);
+        new_user.id = match &rejoining {
+            Some(current) => current.id.clone(),
+            None => auth::user_id(),
+        };
+        let user = AuthenticatedUser {
+            id: new_user.id.clone(),
+            name: name.clone(),
+        };
+
+        let response = if self.presence.join(new_user) || rejoining.is_some() {
+            tracing::info!(user = %name, rejoining = rejoining.is_some(), "joined");
+            if let Some(general) = self.rooms.lock().await.get_mut(DEFAULT_ROOM_ID) {
+                general.members.insert(name.clone());
+            }
+            backend::proto::JoinResponse {
                 error: 0,
                 msg: String::from("Success"),
-            }));
+                token: self.sessions.mint(&user),
+            }
         } else {
-            let _ = response.insert(tonic::Response::new(backend::proto::JoinResponse {
+            tracing::debug!(user = %name, "name taken");
+            self.metrics
+                .join_failures
+                .with_label_values(&["name_taken"])
+                .inc();
+            backend::proto::JoinResponse {
                 error: 1,
                 msg: String::from("User already exists."),
-            }));
+                token: String::new(),
+            }
         };
 
-        response.ok_or(tonic::Status::unknown("Error couldn't be determined"))
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn leave(
+        &self,
+        request: tonic::Request<backend::proto::Empty>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        if !self.presence.leave(&user.name) {
+            return Err(tonic::Status::not_found("User is not logged in."));
+        }
+        // Right away rather than once `forget_departed` sees the departure,
+        // so the token is refused as soon as Leave returns.
+        self.sessions.revoke_user(&user.id);
+        tracing::info!(user = %user.name, "left");
+        Ok(tonic::Response::new(backend::proto::Empty {}))
     }
 
     async fn send_msg(
         &self,
         request: tonic::Request<backend::proto::ChatMessage>,
     ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
-        println!("[send_msg] Method called");
-        let msg = dbg!(request.into_inner());
-        let mut observers = self.messages.lock().await;
-
-        // Send to all observers and collect indices of failed sends
-        let mut failed_indices = Vec::new();
-        for (idx, observer) in observers.iter().enumerate() {
-            let observer = Arc::clone(&observer);
-            let msg = msg.clone();
-
-            // Try to send, mark for removal if channel is closed
-            if observer.lock().await.send(Ok(msg)).await.is_err() {
-                failed_indices.push(idx);
-            }
+        let from = auth::authenticated(&request)?;
+        let mut msg = request.into_inner();
+        msg.msg = check_text(&msg.msg)?;
+        msg.from = from.name;
+        msg.to = String::new();
+        msg.room_id = room_or_default(msg.room_id);
+        if direct_participants(&msg.room_id).is_some() {
+            return Err(tonic::Status::invalid_argument(
+                "Use SendDirect for direct messages.",
+            ));
         }
 
-        // Remove disconnected observers (iterate in reverse to maintain indices)
-        for idx in failed_indices.into_iter().rev() {
-            observers.remove(idx);
-            println!("[send_msg] Removed disconnected receiver at index {}", idx);
+        let mut rooms = self.rooms.lock().await;
+        let room = rooms
+            .get_mut(&msg.room_id)
+            .ok_or_else(|| tonic::Status::not_found("Room does not exist."))?;
+        if !room.members.contains(&msg.from) {
+            return Err(tonic::Status::permission_denied(
+                "User is not a member of this room.",
+            ));
         }
+        self.publish(room, msg).await?;
+
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    /// Sends a private message that only the sender's and the recipient's
+    /// streams of their direct conversation receive.
+    async fn send_direct(
+        &self,
+        request: tonic::Request<backend::proto::DirectMessage>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let from = auth::authenticated(&request)?;
+        let direct = request.into_inner();
+        let text = check_text(&direct.msg)?;
+        if direct.to == from.name {
+            return Err(tonic::Status::invalid_argument(
+                "Can't send a direct message to yourself.",
+            ));
+        }
+        let to_id = self
+            .presence
+            .id_of(&direct.to)
+            .ok_or_else(|| tonic::Status::not_found("Recipient is not logged in."))?;
+
+        let room_id = direct_room_id(&from.id, &to_id);
+        let msg = backend::proto::ChatMessage {
+            from: from.name,
+            to: direct.to,
+            msg: text,
+            room_id,
+            parent_id: direct.parent_id,
+            ..Default::default()
+        };
+        let mut rooms = self.rooms.lock().await;
+        let room = self.room(&mut rooms, &msg.room_id).await?;
+        self.publish(room, msg).await?;
 
         Ok(tonic::Response::new(backend::proto::Empty {}))
     }
 
-    /// Returns a stream of chat messages. Receivers are automatically cleaned up
-    /// when clients disconnect (handled in send_msg).
+    /// Names the direct conversation with `user` after them. Only needs the
+    /// other user to be logged in; the room itself is created on first use.
+    async fn get_direct_room(
+        &self,
+        request: tonic::Request<backend::proto::DirectRoomRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Room>> {
+        let user = auth::authenticated(&request)?;
+        let partner = request.into_inner().user;
+        if partner == user.name {
+            return Err(tonic::Status::invalid_argument(
+                "Can't open a direct conversation with yourself.",
+            ));
+        }
+        let partner_id = self
+            .presence
+            .id_of(&partner)
+            .ok_or_else(|| tonic::Status::not_found("User is not logged in."))?;
+        Ok(tonic::Response::new(backend::proto::Room {
+            id: direct_room_id(&user.id, &partner_id),
+            name: partner,
+            owner: String::new(),
+        }))
+    }
+
+    /// Returns a stream of chat messages for a single room, starting with any
+    /// missed since `after_id`. The stream ends with RESOURCE_EXHAUSTED if the
+    /// client can't keep up, and once the session is revoked. The requesting
+    /// user counts as online until the stream is dropped.
     async fn recieve_msg(
         &self,
-        _request: tonic::Request<backend::proto::Empty>,
+        request: tonic::Request<backend::proto::RecieveMsgRequest>,
     ) -> tonic::Result<tonic::Response<Self::RecieveMsgStream>> {
-        println!("[recieve_msg] Method called");
-        let (sender, receiver) = tokio::sync::mpsc::channel(1000);
+        let user = auth::authenticated(&request)?;
+        let revocation = auth::revocation(&request).unwrap_or_default();
+        let request = request.into_inner();
+        let room_id = room_or_default(request.room_id);
+        check_access(&room_id, &user.id)?;
+        // Replay and subscribe under one lock so nothing is missed or repeated
+        // in between.
+        let mut rooms = self.rooms.lock().await;
+        let room = self.room(&mut rooms, &room_id).await?;
+        let replay = match request.after_id {
+            None => Vec::new(),
+            Some(after_id) => room.replay(after_id).ok_or_else(|| {
+                tonic::Status::out_of_range("Too far behind to resume, reload the history.")
+            })?,
+        };
+        let live = Observed::new(room.hub.subscribe(), Arc::clone(&self.metrics));
+        drop(rooms);
 
-        self.messages
-            .lock()
-            .await
-            .push(Arc::new(Mutex::new(sender)));
+        let stream = tokio_stream::iter(replay.into_iter().map(Ok)).chain(live);
+        let farewell = backend::proto::ChatEvent {
+            seq: 0,
+            event: Some(Event::Shutdown(backend::proto::Shutdown {
+                reason: String::from("Server shutting down."),
+            })),
+        };
+        let stream = self.shutdown.wrap(stream, Some(Ok(farewell)));
+        let stream = revocation.wrap(stream, None);
+        let connection = self.presence.connect(&user.name);
+        Ok(tonic::Response::new(Tracked::new(
+            Box::pin(stream),
+            connection,
+        )))
+    }
+
+    async fn edit_message(
+        &self,
+        request: tonic::Request<backend::proto::EditMessageRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let request = request.into_inner();
+        if request.msg.trim().is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "Message cannot be empty, delete it instead.",
+            ));
+        }
+        let text = check_text(&request.msg)?;
+        self.change(
+            request.id,
+            &user,
+            |msg, _| msg.from == user.name,
+            |msg| {
+                if msg.msg == text {
+                    return false;
+                }
+                msg.msg = text;
+                msg.edited = true;
+                true
+            },
+            Event::Edited,
+        )
+        .await?;
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    /// Replaces the message with a tombstone. Its author and the owner of
+    /// the room may delete it.
+    async fn delete_message(
+        &self,
+        request: tonic::Request<backend::proto::DeleteMessageRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let request = request.into_inner();
+        self.change(
+            request.id,
+            &user,
+            |msg, room| {
+                msg.from == user.name
+                    || (!room.room.owner.is_empty() && room.room.owner == user.name)
+            },
+            |msg| {
+                msg.msg.clear();
+                msg.reactions.clear();
+                msg.deleted = true;
+                true
+            },
+            Event::Deleted,
+        )
+        .await?;
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    async fn add_reaction(
+        &self,
+        request: tonic::Request<backend::proto::ReactionRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let request = request.into_inner();
+        let emoji = check_emoji(request.emoji)?;
+        self.change(
+            request.message_id,
+            &user,
+            |_, room| room.may_post(&user.name),
+            |msg| {
+                let idx = match msg.reactions.iter().position(|r| r.emoji == emoji) {
+                    Some(idx) => idx,
+                    None => {
+                        msg.reactions.push(backend::proto::Reaction {
+                            emoji,
+                            users: Vec::new(),
+                        });
+                        msg.reactions.len() - 1
+                    }
+                };
+                let users = &mut msg.reactions[idx].users;
+                if users.contains(&user.name) {
+                    return false;
+                }
+                users.push(user.name.clone());
+                true
+            },
+            Event::Reactions,
+        )
+        .await?;
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    async fn remove_reaction(
+        &self,
+        request: tonic::Request<backend::proto::ReactionRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let request = request.into_inner();
+        self.change(
+            request.message_id,
+            &user,
+            |_, _| true,
+            |msg| {
+                let Some(idx) = msg.reactions.iter().position(|r| r.emoji == request.emoji) else {
+                    return false;
+                };
+                let users = &mut msg.reactions[idx].users;
+                let before = users.len();
+                users.retain(|name| *name != user.name);
+                let removed = users.len() != before;
+                if users.is_empty() {
+                    msg.reactions.remove(idx);
+                }
+                removed
+            },
+            Event::Reactions,
+        )
+        .await?;
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    /// Marks the user as typing in a room until they post, clear it, or
+    /// `TYPING_TIMEOUT` passes without a renewal.
+    async fn set_typing(
+        &self,
+        request: tonic::Request<backend::proto::SetTypingRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let request = request.into_inner();
+        let room_id = room_or_default(request.room_id);
+        check_access(&room_id, &user.id)?;
+        let user = user.name;
 
-        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
-        // Err(tonic::Status::unimplemented(""))
+        let mut rooms = self.rooms.lock().await;
+        let room = self.room(&mut rooms, &room_id).await?;
+        if !room.may_post(&user) {
+            return Err(tonic::Status::permission_denied(
+                "User is not a member of this room.",
+            ));
+        }
+        if !request.typing {
+            room.stop_typing(&user);
+        } else if room.start_typing(&user) {
+            // Renewals only move the deadline this task waits for.
+            tokio::spawn(expire_typing(Arc::clone(&self.rooms), room_id, user));
+        }
+        Ok(tonic::Response::new(backend::proto::Empty {}))
     }
 
     async fn get_all_users(
         &self,
         _request: tonic::Request<backend::proto::Empty>,
     ) -> Result<tonic::Response<backend::proto::UserList>, tonic::Status> {
-        println!("[get_all_users] Method called");
-        let user_list = self.user_list.lock().await;
-        Ok(tonic::Response::new(user_list.clone()))
+        Ok(tonic::Response::new(backend::proto::UserList {
+            users: self.presence.users(),
+        }))
     }
 
-    type RecieveMsgStream = ReceiverStream<tonic::Result<backend::proto::ChatMessage>>;
+    /// Streams the member list, then every change to it. Watching with a
+    /// session ends once the session is revoked.
+    async fn watch_presence(
+        &self,
+        request: tonic::Request<backend::proto::Empty>,
+    ) -> tonic::Result<tonic::Response<Self::WatchPresenceStream>> {
+        let revocation = auth::revocation(&request).unwrap_or_default();
+        let (snapshot, receiver) = self.presence.watch();
+        let live = BroadcastStream::new(receiver).map(|event| {
+            event.map_err(|BroadcastStreamRecvError::Lagged(missed)| {
+                tonic::Status::resource_exhausted(format!(
+                    "Presence watcher fell behind by {} events",
+                    missed
+                ))
+            })
+        });
+        let stream = tokio_stream::iter(snapshot.into_iter().map(Ok)).chain(live);
+        let stream = revocation.wrap(self.shutdown.wrap(stream, None), None);
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
+    /// Returns a page of top-level messages and how many replies each has.
+    async fn get_history(
+        &self,
+        request: tonic::Request<backend::proto::HistoryRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::History>> {
+        // Only needed for direct conversations, rooms are public.
+        let user = auth::authenticated(&request);
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => self.limits.history,
+            limit => (limit as usize).min(self.limits.max_history),
+        };
+        let room_id = room_or_default(request.room_id);
+        if direct_participants(&room_id).is_some() {
+            check_access(&room_id, &user?.id)?;
+        }
+        // Every change up to here is stored: ids and revisions are assigned
+        // under the rooms lock, which is held until the change is stored.
+        let last_seq = {
+            let _rooms = self.rooms.lock().await;
+            self.last_id.load(Ordering::SeqCst)
+        };
+        let page = match (request.before_id, request.after_id) {
+            (0, 0) => Page::Latest,
+            (0, after_id) => Page::After(after_id),
+            (before_id, _) => Page::Before(before_id),
+        };
+        // One extra message tells whether there is another page.
+        let mut messages = self.storage.history(&room_id, page, limit + 1).await?;
+        let has_more = messages.len() > limit;
+        if has_more {
+            // The extra one is at the far end of the page.
+            match page {
+                Page::After(_) => messages.truncate(limit),
+                Page::Latest | Page::Before(_) => {
+                    messages.remove(0);
+                }
+            }
+        }
+        let ids: Vec<u64> = messages.iter().map(|msg| msg.id).collect();
+        let reply_counts = self.storage.reply_counts(&ids, last_seq).await?;
+        Ok(tonic::Response::new(backend::proto::History {
+            messages,
+            has_more,
+            reply_counts,
+            last_seq,
+        }))
+    }
+
+    /// Returns a message and every reply to it.
+    async fn get_thread(
+        &self,
+        request: tonic::Request<backend::proto::ThreadRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Thread>> {
+        let user = auth::authenticated(&request);
+        let request = request.into_inner();
+        let parent = self
+            .storage
+            .message(request.message_id)
+            .await?
+            .ok_or_else(|| tonic::Status::not_found("Message does not exist."))?;
+        if direct_participants(&parent.room_id).is_some() {
+            check_access(&parent.room_id, &user?.id)?;
+        }
+        let replies = self.storage.replies(&[parent.id]).await?;
+        Ok(tonic::Response::new(backend::proto::Thread {
+            parent: Some(parent),
+            replies,
+        }))
+    }
+
+    async fn search_messages(
+        &self,
+        request: tonic::Request<backend::proto::SearchRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::SearchResponse>> {
+        // Without a session only rooms are searched.
+        let user = auth::authenticated(&request).ok();
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => self.limits.search,
+            limit => (limit as usize).min(self.limits.max_search),
+        };
+        let filter = Filter {
+            from: Some(request.from.as_str()).filter(|from| !from.is_empty()),
+            room_id: Some(request.room_id.as_str()).filter(|room_id| !room_id.is_empty()),
+            after: request.after.map(|time| time.seconds),
+            before: request.before.map(|time| time.seconds),
+            before_id: Some(request.page_token).filter(|token| *token != 0),
+        };
+        let visible = |room_id: &str| match &user {
+            Some(user) => check_access(room_id, &user.id).is_ok(),
+            None => direct_participants(room_id).is_none(),
+        };
+
+        let (hits, more) = self
+            .search
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .search(&request.query, &filter, visible, limit);
+        let next_page_token = match hits.last() {
+            Some(hit) if more => hit.message.as_ref().map_or(0, |msg| msg.id),
+            _ => 0,
+        };
+        Ok(tonic::Response::new(backend::proto::SearchResponse {
+            hits,
+            next_page_token,
+        }))
+    }
+
+    async fn create_room(
+        &self,
+        request: tonic::Request<backend::proto::CreateRoomRequest>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Room>> {
+        let owner = auth::authenticated(&request)?.name;
+        let name = request.into_inner().name.trim().to_string();
+        let id = room_id_from_name(&name);
+        if id.is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "Room name must contain letters or digits.",
+            ));
+        }
+
+        let mut rooms = self.rooms.lock().await;
+        if rooms.contains_key(&id) {
+            return Err(tonic::Status::already_exists("Room already exists."));
+        }
+
+        let room = backend::proto::Room { id, name, owner };
+        self.storage.save_room(room.clone()).await?;
+        rooms.insert(
+            room.id.clone(),
+            RoomState {
+                room: room.clone(),
+                ..Default::default()
+            },
+        );
+
+        Ok(tonic::Response::new(room))
+    }
+
+    async fn list_rooms(
+        &self,
+        _request: tonic::Request<backend::proto::Empty>,
+    ) -> tonic::Result<tonic::Response<backend::proto::RoomList>> {
+        let mut rooms: Vec<_> = self
+            .rooms
+            .lock()
+            .await
+            .values()
+            .filter(|state| direct_participants(&state.room.id).is_none())
+            .map(|state| state.room.clone())
+            .collect();
+        rooms.sort_by_key(|room| room.name.to_lowercase());
+        Ok(tonic::Response::new(backend::proto::RoomList { rooms }))
+    }
+
+    async fn join_room(
+        &self,
+        request: tonic::Request<backend::proto::RoomMembership>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let membership = request.into_inner();
+        self.rooms
+            .lock()
+            .await
+            .get_mut(&room_or_default(membership.room_id))
+            .ok_or_else(|| tonic::Status::not_found("Room does not exist."))?
+            .members
+            .insert(user.name);
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    async fn leave_room(
+        &self,
+        request: tonic::Request<backend::proto::RoomMembership>,
+    ) -> tonic::Result<tonic::Response<backend::proto::Empty>> {
+        let user = auth::authenticated(&request)?;
+        let membership = request.into_inner();
+        self.rooms
+            .lock()
+            .await
+            .get_mut(&room_or_default(membership.room_id))
+            .ok_or_else(|| tonic::Status::not_found("Room does not exist."))?
+            .members
+            .remove(&user.name);
+        Ok(tonic::Response::new(backend::proto::Empty {}))
+    }
+
+    type RecieveMsgStream = Tracked<EventStream>;
+    type WatchPresenceStream =
+        Pin<Box<dyn Stream<Item = tonic::Result<backend::proto::PresenceEvent>> + Send>>;
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50051".parse().unwrap();
-    let chat_service = Chat::default();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    let _telemetry = telemetry::init(&config.log_level, config.otlp_endpoint.as_deref())?;
 
-    println!("ChatServer listening on: {}", addr);
+    let metrics = Metrics::new();
+    let chat_service = match &config.db {
+        Some(path) => {
+            tracing::info!("Storing messages in: {}", path.display());
+            Chat::new(
+                SqliteStore::open(path)?,
+                config.limits.clone(),
+                Arc::clone(&metrics),
+            )
+            .await?
+        }
+        None => {
+            Chat::new(
+                MemoryStore::default(),
+                config.limits.clone(),
+                Arc::clone(&metrics),
+            )
+            .await?
+        }
+    };
 
-    Server::builder()
-        .add_service(ChatServiceServer::new(chat_service))
-        .serve(addr)
-        .await?;
+    tracing::info!("ChatServer listening on: {}", config.listen);
+
+    let interceptor = auth::interceptor(Arc::clone(&chat_service.sessions));
+    let rate_limit = RateLimitLayer::new(Arc::clone(&chat_service.sessions), config.send_quota);
+    let storage = Arc::clone(&chat_service.storage);
+    let shutdown = chat_service.shutdown.clone();
+    let (reporter, health) = tonic_health::server::health_reporter();
+    tokio::spawn(report_health(Arc::clone(&storage), reporter));
+    // Lets tools like grpcurl list and call the services without the protos.
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(backend::proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build_v1()?;
+    let served = [
+        backend::proto::FILE_DESCRIPTOR_SET,
+        tonic_health::pb::FILE_DESCRIPTOR_SET,
+        tonic_reflection::pb::v1::FILE_DESCRIPTOR_SET,
+    ];
+    let mut server = Server::builder();
+    if let Some(tls) = &config.tls {
+        server = server.tls_config(tls.server_config()?)?;
+        tracing::info!(
+            "Serving TLS{}",
+            match tls.client_ca {
+                Some(_) => ", client certificates required",
+                None => "",
+            }
+        );
+    }
+    let trace = TraceLayer::new_for_grpc()
+        .make_span_with(telemetry::request_span)
+        .on_failure(DefaultOnFailure::new().level(Level::WARN));
+    // Plain HTTP/1.1 is accepted for Prometheus to scrape `/metrics`.
+    let scrape = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get({
+            let metrics = Arc::clone(&metrics);
+            move || async move { metrics.encode() }
+        }),
+    );
+    let routes = Routes::from(scrape)
+        .add_service(ChatServiceServer::with_interceptor(
+            chat_service,
+            interceptor,
+        ))
+        .add_service(health)
+        .add_service(reflection);
+    let serve = server
+        .accept_http1(true)
+        .layer(trace)
+        .layer(MetricsLayer::new(metrics, &served))
+        .layer(rate_limit)
+        .add_routes(routes)
+        .serve_with_shutdown(config.listen, shutdown.started());
+    tokio::pin!(serve);
+
+    tokio::select! {
+        result = &mut serve => result?,
+        () = shutdown::signal() => {
+            tracing::info!(
+                "Shutting down, draining calls for up to {:?}",
+                config.drain_timeout
+            );
+            // Ends the event streams and stops accepting calls.
+            shutdown.begin();
+            match tokio::time::timeout(config.drain_timeout, &mut serve).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    tracing::warn!("Calls still in flight after the drain timeout, dropping them")
+                }
+            }
+        }
+    }
+    storage.flush().await?;
+    tracing::info!("Shut down");
     Ok(())
 }
@@ -0,0 +1,363 @@
+//! Prometheus metrics, served as text on `/metrics`.
+//!
+//! [`MetricsLayer`] wraps the gRPC server and records every call by method
+//! and final status. Only methods of the served services get a label of
+//! their own, so made-up paths can't create new series. The chat service records its own metrics: messages
+//! accepted, join failures, and through [`Observed`] the subscribers
+//! connected, how long messages take to reach them and how many fall behind.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
+
+use futures::Stream;
+use http_body::{Body, Frame};
+use pin_project_lite::pin_project;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use prost::Message;
+use tower::{Layer, Service};
+
+use crate::proto::chat_event::Event;
+use crate::proto::ChatEvent;
+
+/// Method label of calls to paths no served service has.
+const UNKNOWN_METHOD: &str = "unknown";
+
+/// Buckets for delays that are usually well under a millisecond but may
+/// reach seconds under load.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    /// Streams of chat events currently open.
+    pub subscribers: IntGauge,
+    /// Messages accepted, direct ones included.
+    pub messages: IntCounter,
+    /// From a message being accepted to a subscriber's stream yielding it.
+    pub fanout: Histogram,
+    /// Streams ended for falling too far behind.
+    pub lagged: IntCounter,
+    /// Joins refused, by reason.
+    pub join_failures: IntCounterVec,
+    /// Calls handled, by method and status code.
+    pub calls: IntCounterVec,
+    /// From a call arriving to its response ending, by method.
+    pub call_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        let registry = Registry::new();
+        let subscribers = IntGauge::new("chat_subscribers", "Chat event streams currently open.")
+            .expect("valid metric");
+        let messages =
+            IntCounter::new("chat_messages_total", "Messages accepted.").expect("valid metric");
+        let fanout = Histogram::with_opts(
+            HistogramOpts::new(
+                "chat_fanout_seconds",
+                "Time from a message being accepted to a subscriber receiving it.",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .expect("valid metric");
+        let lagged = IntCounter::new(
+            "chat_lagged_subscribers_total",
+            "Event streams ended for falling too far behind.",
+        )
+        .expect("valid metric");
+        let join_failures = IntCounterVec::new(
+            Opts::new("chat_join_failures_total", "Joins refused, by reason."),
+            &["reason"],
+        )
+        .expect("valid metric");
+        let calls = IntCounterVec::new(
+            Opts::new(
+                "grpc_server_handled_total",
+                "gRPC calls handled, by method and status code.",
+            ),
+            &["method", "code"],
+        )
+        .expect("valid metric");
+        let call_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "grpc_server_handling_seconds",
+                "Time from a gRPC call arriving to its response ending, by method.",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method"],
+        )
+        .expect("valid metric");
+
+        for collector in [
+            Box::new(subscribers.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(messages.clone()),
+            Box::new(fanout.clone()),
+            Box::new(lagged.clone()),
+            Box::new(join_failures.clone()),
+            Box::new(calls.clone()),
+            Box::new(call_duration.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Arc::new(Self {
+            registry,
+            subscribers,
+            messages,
+            fanout,
+            lagged,
+            join_failures,
+            calls,
+            call_duration,
+        })
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut text = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut text)
+            .expect("metrics encode");
+        String::from_utf8(text).expect("metrics are UTF-8")
+    }
+}
+
+/// Wraps a subscriber's live events, counting it in
+/// [`Metrics::subscribers`] while open.
+pub struct Observed<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Observed<S> {
+    pub fn new(inner: S, metrics: Arc<Metrics>) -> Self {
+        metrics.subscribers.inc();
+        Self { inner, metrics }
+    }
+}
+
+impl<S> Drop for Observed<S> {
+    fn drop(&mut self) {
+        self.metrics.subscribers.dec();
+    }
+}
+
+impl<S> Stream for Observed<S>
+where
+    S: Stream<Item = tonic::Result<ChatEvent>> + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = Pin::new(&mut self.inner).poll_next(cx);
+        match &item {
+            Poll::Ready(Some(Ok(ChatEvent {
+                event: Some(Event::NewMessage(msg)),
+                ..
+            }))) => {
+                let delay = msg
+                    .sent_at
+                    .and_then(|sent_at| SystemTime::try_from(sent_at).ok())
+                    .and_then(|sent_at| sent_at.elapsed().ok());
+                if let Some(delay) = delay {
+                    self.metrics.fanout.observe(delay.as_secs_f64());
+                }
+            }
+            Poll::Ready(Some(Err(status))) if status.code() == tonic::Code::ResourceExhausted => {
+                self.metrics.lagged.inc();
+            }
+            _ => {}
+        }
+        item
+    }
+}
+
+/// Records gRPC calls in [`Metrics::calls`] and [`Metrics::call_duration`].
+/// Other requests, like those for `/metrics`, pass through unrecorded.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    /// Paths of the served methods, e.g. `/chat.ChatService/SendMsg`.
+    methods: Arc<HashSet<String>>,
+}
+
+impl MetricsLayer {
+    /// Labels calls with the methods of the services in `descriptor_sets`,
+    /// encoded `FileDescriptorSet`s like the ones served for reflection.
+    /// Calls to any other path are labelled `"unknown"`.
+    pub fn new(metrics: Arc<Metrics>, descriptor_sets: &[&[u8]]) -> Self {
+        let mut methods = HashSet::new();
+        for set in descriptor_sets {
+            let set = prost_types::FileDescriptorSet::decode(*set).expect("valid descriptor set");
+            for file in set.file {
+                for service in &file.service {
+                    for method in &service.method {
+                        methods.insert(format!(
+                            "/{}.{}/{}",
+                            file.package(),
+                            service.name(),
+                            method.name()
+                        ));
+                    }
+                }
+            }
+        }
+        Self {
+            metrics,
+            methods: Arc::new(methods),
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: Arc::clone(&self.metrics),
+            methods: Arc::clone(&self.methods),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+    methods: Arc<HashSet<String>>,
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<MeteredBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let grpc = request
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"application/grpc"));
+        let call = grpc.then(|| Call {
+            metrics: Arc::clone(&self.metrics),
+            method: self
+                .methods
+                .get(request.uri().path())
+                .map_or(UNKNOWN_METHOD, String::as_str)
+                .to_string(),
+            started: Instant::now(),
+        });
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            // Failures without a body carry their status in the headers.
+            let code = grpc_status(response.headers());
+            Ok(response.map(|inner| MeteredBody { inner, call, code }))
+        })
+    }
+}
+
+/// A gRPC call waiting for its status.
+struct Call {
+    metrics: Arc<Metrics>,
+    method: String,
+    started: Instant,
+}
+
+impl Call {
+    fn finish(self, code: tonic::Code) {
+        self.metrics
+            .calls
+            .with_label_values(&[self.method.as_str(), &format!("{:?}", code)])
+            .inc();
+        self.metrics
+            .call_duration
+            .with_label_values(&[self.method.as_str()])
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+fn grpc_status(headers: &http::HeaderMap) -> Option<tonic::Code> {
+    headers
+        .get("grpc-status")
+        .map(|value| tonic::Code::from_bytes(value.as_bytes()))
+}
+
+pin_project! {
+    /// A response body that records its call once the status arrives in the
+    /// trailers, or once it ends or is dropped without one.
+    pub struct MeteredBody<B> {
+        #[pin]
+        inner: B,
+        call: Option<Call>,
+        code: Option<tonic::Code>,
+    }
+
+    impl<B> PinnedDrop for MeteredBody<B> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if let Some(call) = this.call.take() {
+                // Without a status, the client went away before the end.
+                call.finish(this.code.unwrap_or(tonic::Code::Cancelled));
+            }
+        }
+    }
+}
+
+impl<B: Body> Body for MeteredBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = this.inner.poll_frame(cx);
+        match &frame {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(code) = frame.trailers_ref().and_then(grpc_status) {
+                    *this.code = Some(code);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                this.code.get_or_insert(tonic::Code::Internal);
+            }
+            Poll::Ready(None) => {
+                if let Some(call) = this.call.take() {
+                    call.finish(this.code.unwrap_or(tonic::Code::Unknown));
+                }
+            }
+            Poll::Pending => {}
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,250 @@
+//! Tracks which users are logged in and whether they are currently connected.
+//!
+//! A user is online while at least one of their `recieve_msg` streams is
+//! open. When the last one closes they become away, and if they don't
+//! reconnect within the grace period they are removed so their name can be
+//! taken again.
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream;
+use tokio::sync::broadcast;
+
+use crate::proto::presence_event::Kind;
+use crate::proto::{PresenceEvent, PresenceStatus, User};
+
+/// Number of presence events buffered for slow `WatchPresence` clients before
+/// they are told they lagged behind.
+const EVENT_BUFFER: usize = 256;
+
+struct Member {
+    user: User,
+    connections: usize,
+    /// Bumped on every connect and disconnect so a pending expiry can tell
+    /// whether the user came back in the meantime.
+    generation: u64,
+}
+
+pub struct Presence {
+    members: Mutex<BTreeMap<String, Member>>,
+    events: broadcast::Sender<PresenceEvent>,
+    grace: Duration,
+}
+
+impl Presence {
+    pub fn new(grace: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            members: Mutex::new(BTreeMap::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            grace,
+        })
+    }
+
+    // The lock is never held across an await, so a std mutex lets `Drop`
+    // update presence without spawning.
+    fn members(&self) -> MutexGuard<'_, BTreeMap<String, Member>> {
+        self.members
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn emit(&self, kind: Kind, member: &Member) {
+        let mut user = member.user.clone();
+        user.status = status(member.connections).into();
+        // No watchers is fine, nothing to deliver.
+        let _ = self.events.send(PresenceEvent {
+            kind: kind.into(),
+            user: Some(user),
+        });
+    }
+
+    /// Adds `user` to the member list. Returns `false` if the name is taken.
+    pub fn join(self: &Arc<Self>, user: User) -> bool {
+        let mut members = self.members();
+        if members.contains_key(&user.name) {
+            return false;
+        }
+
+        let name = user.name.clone();
+        let member = Member {
+            user,
+            connections: 0,
+            generation: 0,
+        };
+        self.emit(Kind::Joined, &member);
+        members.insert(name.clone(), member);
+        drop(members);
+
+        // Users that never open a stream are cleaned up like disconnected ones.
+        self.schedule_expiry(name, 0);
+        true
+    }
+
+    /// Removes `name` from the member list. Returns `false` if they weren't
+    /// logged in.
+    pub fn leave(&self, name: &str) -> bool {
+        match self.members().remove(name) {
+            Some(member) => {
+                self.emit(Kind::Left, &member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.members().contains_key(name)
+    }
+
+    /// Returns the id of the user currently called `name`.
+    pub fn id_of(&self, name: &str) -> Option<String> {
+        self.members()
+            .get(name)
+            .map(|member| member.user.id.clone())
+    }
+
+    /// Whether the user with id `id` is still logged in.
+    pub fn contains_id(&self, id: &str) -> bool {
+        self.members().values().any(|member| member.user.id == id)
+    }
+
+    /// Returns every logged in user with their current status.
+    pub fn users(&self) -> Vec<User> {
+        self.members()
+            .values()
+            .map(|member| User {
+                status: status(member.connections).into(),
+                ..member.user.clone()
+            })
+            .collect()
+    }
+
+    /// Marks `name` online until the returned guard is dropped. Returns
+    /// `None` if they aren't logged in.
+    pub fn connect(self: &Arc<Self>, name: &str) -> Option<Connection> {
+        let mut members = self.members();
+        let member = members.get_mut(name)?;
+        member.connections += 1;
+        member.generation += 1;
+        if member.connections == 1 {
+            self.emit(Kind::Online, member);
+        }
+        Some(Connection {
+            presence: Arc::clone(self),
+            name: name.to_string(),
+        })
+    }
+
+    fn disconnect(self: &Arc<Self>, name: &str) {
+        let mut members = self.members();
+        let Some(member) = members.get_mut(name) else {
+            return;
+        };
+        member.connections -= 1;
+        member.generation += 1;
+        if member.connections == 0 {
+            self.emit(Kind::Away, member);
+            let generation = member.generation;
+            drop(members);
+            self.schedule_expiry(name.to_string(), generation);
+        }
+    }
+
+    /// Removes `name` after the grace period unless they connected or
+    /// disconnected again in the meantime.
+    fn schedule_expiry(self: &Arc<Self>, name: String, generation: u64) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let presence = Arc::clone(self);
+        runtime.spawn(async move {
+            tokio::time::sleep(presence.grace).await;
+            let mut members = presence.members();
+            let expired = members
+                .get(&name)
+                .is_some_and(|member| member.connections == 0 && member.generation == generation);
+            if expired {
+                if let Some(member) = members.remove(&name) {
+                    tracing::info!(user = %name, "timed out");
+                    presence.emit(Kind::Left, &member);
+                }
+            }
+        });
+    }
+
+    /// Returns the current members as events, followed by a receiver for
+    /// every change after that snapshot.
+    pub fn watch(&self) -> (Vec<PresenceEvent>, broadcast::Receiver<PresenceEvent>) {
+        // Subscribe while holding the lock so no change falls between the
+        // snapshot and the live events.
+        let members = self.members();
+        let receiver = self.events.subscribe();
+        let snapshot = members
+            .values()
+            .map(|member| PresenceEvent {
+                kind: match member.connections {
+                    0 => Kind::Away,
+                    _ => Kind::Online,
+                }
+                .into(),
+                user: Some(User {
+                    status: status(member.connections).into(),
+                    ..member.user.clone()
+                }),
+            })
+            .collect();
+        (snapshot, receiver)
+    }
+
+    /// Subscribes to changes only, without the initial snapshot.
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceEvent> {
+        self.events.subscribe()
+    }
+}
+
+fn status(connections: usize) -> PresenceStatus {
+    match connections {
+        0 => PresenceStatus::Away,
+        _ => PresenceStatus::Online,
+    }
+}
+
+/// Keeps a user online while alive. Created by [`Presence::connect`].
+pub struct Connection {
+    presence: Arc<Presence>,
+    name: String,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.presence.disconnect(&self.name);
+    }
+}
+
+/// Wraps a response stream so that the user's presence lasts exactly as long
+/// as the client keeps the stream open.
+pub struct Tracked<S> {
+    inner: S,
+    _connection: Option<Connection>,
+}
+
+impl<S> Tracked<S> {
+    pub fn new(inner: S, connection: Option<Connection>) -> Self {
+        Self {
+            inner,
+            _connection: connection,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for Tracked<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
@@ -0,0 +1,120 @@
+//! Rate limiting of calls, keyed by the user of the session.
+//!
+//! [`RateLimitLayer`] gives every user a token bucket for sending messages,
+//! and another for typing notifications.
+//! `Join` isn't limited here: it has no session yet to key a bucket by, so
+//! the frontend limits it per client IP instead.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use chat_common::ratelimit::{refusal_message, retry_after};
+use tonic::metadata::MetadataValue;
+use tower::{Layer, Service};
+
+use crate::auth::{self, Sessions};
+
+pub use chat_common::ratelimit::{Quota, RateLimiter};
+
+/// Methods limited by [`RateLimitLayer`], sharing one bucket per user.
+const SEND_METHODS: &[&str] = &["/chat.ChatService/SendMsg", "/chat.ChatService/SendDirect"];
+
+/// Limited with the same quota as sends, but in a bucket of its own so
+/// typing never costs a message.
+const TYPING_METHOD: &str = "/chat.ChatService/SetTyping";
+
+/// Refuses sends and typing updates beyond a user's [`Quota`] with
+/// `RESOURCE_EXHAUSTED`, saying when to retry in the message and in
+/// `retry-after` metadata (whole seconds). Calls without a valid session
+/// pass, for the service to refuse. That includes `Join`, which the frontend
+/// limits per client IP.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    sessions: Arc<Sessions>,
+    sends: Arc<RateLimiter<String>>,
+    typing: Arc<RateLimiter<String>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(sessions: Arc<Sessions>, quota: Quota) -> Self {
+        Self {
+            sessions,
+            sends: Arc::new(RateLimiter::new(quota)),
+            typing: Arc::new(RateLimiter::new(quota)),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limits: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limits: RateLimitLayer,
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path();
+        let limiter = if SEND_METHODS.contains(&path) {
+            Some(&self.limits.sends)
+        } else if path == TYPING_METHOD {
+            Some(&self.limits.typing)
+        } else {
+            None
+        };
+        if let Some(limiter) = limiter {
+            let user = request
+                .headers()
+                .get(http::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(auth::bearer_token)
+                .and_then(|token| self.limits.sessions.user(token));
+            if let Some(user) = user {
+                if let Err(wait) = limiter.check(user.id) {
+                    let response = refusal(wait).into_http();
+                    return Box::pin(async move { Ok(response) });
+                }
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+fn refusal(wait: Duration) -> tonic::Status {
+    let seconds = retry_after(wait);
+    let mut status =
+        tonic::Status::resource_exhausted(refusal_message("Sending too fast", seconds));
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(seconds));
+    status
+}
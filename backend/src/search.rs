@@ -0,0 +1,312 @@
+//! In-memory inverted index over message text for `SearchMessages`.
+//!
+//! Words are lowercased runs of letters and digits. A query matches messages
+//! that contain every one of its words; the last word also matches as a
+//! prefix, so results show up while the user is still typing it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use crate::proto::{ChatMessage, Highlight, SearchHit};
+
+/// Characters of context kept on each side of the first match in a snippet.
+const SNIPPET_CONTEXT: usize = 60;
+
+/// Restricts which messages a search returns, on top of the query words.
+#[derive(Default)]
+pub struct Filter<'a> {
+    pub from: Option<&'a str>,
+    pub room_id: Option<&'a str>,
+    /// Sent at or after, in seconds since the epoch.
+    pub after: Option<i64>,
+    /// Sent before, in seconds since the epoch.
+    pub before: Option<i64>,
+    /// Only return messages with a smaller id, for pagination.
+    pub before_id: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    /// Word to the ids of the messages containing it.
+    postings: HashMap<String, BTreeSet<u64>>,
+    /// Every indexed message by id, for filtering and snippets.
+    messages: BTreeMap<u64, ChatMessage>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes `msg`, replacing an earlier version with the same id. Deleted
+    /// messages are dropped from the index.
+    pub fn insert(&mut self, msg: ChatMessage) {
+        self.remove(msg.id);
+        if msg.deleted {
+            return;
+        }
+        for (word, _) in words(&msg.msg) {
+            self.postings.entry(word).or_default().insert(msg.id);
+        }
+        self.messages.insert(msg.id, msg);
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let Some(msg) = self.messages.remove(&id) else {
+            return;
+        };
+        for (word, _) in words(&msg.msg) {
+            if let Some(ids) = self.postings.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Returns up to `limit` hits for `query`, newest first, and whether there
+    /// are more. `visible` decides whether the searching user may see a room.
+    pub fn search(
+        &self,
+        query: &str,
+        filter: &Filter,
+        visible: impl Fn(&str) -> bool,
+        limit: usize,
+    ) -> (Vec<SearchHit>, bool) {
+        let terms: Vec<String> = words(query).map(|(word, _)| word).collect();
+        let Some((last, exact)) = terms.split_last() else {
+            return (Vec::new(), false);
+        };
+
+        let mut candidates: Vec<BTreeSet<u64>> = Vec::new();
+        for term in exact {
+            match self.postings.get(term) {
+                Some(ids) => candidates.push(ids.clone()),
+                None => return (Vec::new(), false),
+            }
+        }
+        // The last word may still be incomplete.
+        let prefixed: BTreeSet<u64> = self
+            .postings
+            .iter()
+            .filter(|(word, _)| word.starts_with(last.as_str()))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect();
+        candidates.push(prefixed);
+        candidates.sort_by_key(BTreeSet::len);
+
+        let (smallest, rest) = candidates.split_first().expect("at least one term");
+        let mut matches = smallest
+            .iter()
+            .rev()
+            .filter(|id| filter.before_id.is_none_or(|before| **id < before))
+            .filter(|id| rest.iter().all(|ids| ids.contains(id)))
+            .filter_map(|id| self.messages.get(id))
+            .filter(|msg| filter.matches(msg) && visible(&msg.room_id));
+
+        let hits: Vec<_> = matches
+            .by_ref()
+            .take(limit)
+            .map(|msg| hit(msg, &terms))
+            .collect();
+        let more = matches.next().is_some();
+        (hits, more)
+    }
+}
+
+impl Filter<'_> {
+    fn matches(&self, msg: &ChatMessage) -> bool {
+        let sent = msg.sent_at.as_ref().map_or(0, |time| time.seconds);
+        self.from.is_none_or(|from| msg.from == from)
+            && self.room_id.is_none_or(|room_id| msg.room_id == room_id)
+            && self.after.is_none_or(|after| sent >= after)
+            && self.before.is_none_or(|before| sent < before)
+    }
+}
+
+/// Splits `text` into lowercased words with their byte ranges in `text`.
+fn words(text: &str) -> impl Iterator<Item = (String, Range<usize>)> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| {
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            (word.to_lowercase(), start..start + word.len())
+        })
+}
+
+/// Builds the hit for `msg`, with a snippet around the first match and the
+/// ranges of every matching word in it.
+fn hit(msg: &ChatMessage, terms: &[String]) -> SearchHit {
+    let last = terms.len() - 1;
+    let matched: Vec<Range<usize>> = words(&msg.msg)
+        .filter(|(word, _)| {
+            terms
+                .iter()
+                .enumerate()
+                .any(|(idx, term)| word == term || (idx == last && word.starts_with(term.as_str())))
+        })
+        .map(|(_, range)| range)
+        .collect();
+
+    let text = &msg.msg;
+    let first = matched.first().map_or(0..0, Range::clone);
+    let start = text[..first.start]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(idx, _)| idx);
+    let end = text[first.end..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map_or(text.len(), |(idx, _)| first.end + idx);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let offset = snippet.len();
+    snippet.push_str(&text[start..end]);
+    let highlights = matched
+        .into_iter()
+        .filter(|range| range.start >= start && range.end <= end)
+        .map(|range| Highlight {
+            start: (range.start - start + offset) as u32,
+            end: (range.end - start + offset) as u32,
+        })
+        .collect();
+    if end < text.len() {
+        snippet.push('…');
+    }
+
+    SearchHit {
+        message: Some(msg.clone()),
+        snippet,
+        highlights,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: u64, room_id: &str, text: &str) -> ChatMessage {
+        ChatMessage {
+            id,
+            room_id: room_id.to_string(),
+            from: String::from("alice"),
+            msg: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn index(messages: &[(&str, &str)]) -> SearchIndex {
+        let mut index = SearchIndex::new();
+        for (id, (room_id, text)) in (1..).zip(messages) {
+            index.insert(message(id, room_id, text));
+        }
+        index
+    }
+
+    /// Ids of the hits for `query` with no filter, newest first.
+    fn ids(index: &SearchIndex, query: &str) -> Vec<u64> {
+        let (hits, _) = index.search(query, &Filter::default(), |_| true, 10);
+        hits.iter()
+            .map(|hit| hit.message.as_ref().unwrap().id)
+            .collect()
+    }
+
+    /// The highlighted parts of `hit`'s snippet.
+    fn highlighted(hit: &SearchHit) -> Vec<&str> {
+        hit.highlights
+            .iter()
+            .map(|h| &hit.snippet[h.start as usize..h.end as usize])
+            .collect()
+    }
+
+    #[test]
+    fn matches_every_word_and_the_last_as_a_prefix() {
+        let index = index(&[
+            ("general", "Hello world"),
+            ("general", "hello there"),
+            ("general", "world peace"),
+        ]);
+        assert_eq!(ids(&index, "hel"), [2, 1]);
+        assert_eq!(ids(&index, "HELLO wor"), [1]);
+        assert_eq!(ids(&index, "wor hello"), Vec::<u64>::new());
+        assert_eq!(ids(&index, " ,. "), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn follows_edits_and_deletions() {
+        let mut index = index(&[("general", "apple pie")]);
+        index.insert(message(1, "general", "banana split"));
+        assert_eq!(ids(&index, "apple"), Vec::<u64>::new());
+        assert_eq!(ids(&index, "banana"), [1]);
+
+        index.insert(ChatMessage {
+            deleted: true,
+            ..message(1, "general", "")
+        });
+        assert_eq!(ids(&index, "banana"), Vec::<u64>::new());
+        assert!(index.postings.is_empty());
+    }
+
+    #[test]
+    fn filters_hidden_rooms_and_pages_by_id() {
+        let index = index(&[
+            ("general", "news one"),
+            ("secret", "news two"),
+            ("general", "news three"),
+            ("general", "news four"),
+        ]);
+        let visible = |room_id: &str| room_id != "secret";
+
+        let (hits, more) = index.search("news", &Filter::default(), visible, 2);
+        let page: Vec<_> = hits
+            .iter()
+            .map(|hit| hit.message.as_ref().unwrap().id)
+            .collect();
+        assert_eq!(page, [4, 3]);
+        assert!(more);
+
+        let filter = Filter {
+            before_id: Some(3),
+            ..Default::default()
+        };
+        let (hits, more) = index.search("news", &filter, visible, 2);
+        let page: Vec<_> = hits
+            .iter()
+            .map(|hit| hit.message.as_ref().unwrap().id)
+            .collect();
+        assert_eq!(page, [1]);
+        assert!(!more);
+
+        let filter = Filter {
+            room_id: Some("secret"),
+            ..Default::default()
+        };
+        assert!(index.search("news", &filter, visible, 2).0.is_empty());
+    }
+
+    #[test]
+    fn highlights_every_matching_word() {
+        let msg = message(1, "general", "Rust rocks, RUST rules; rusty nail");
+        let found = hit(&msg, &[String::from("rust")]);
+        assert_eq!(found.snippet, msg.msg);
+        assert_eq!(highlighted(&found), ["Rust", "RUST", "rusty"]);
+
+        let found = hit(&msg, &[String::from("rust"), String::from("nail")]);
+        assert_eq!(highlighted(&found), ["Rust", "RUST", "nail"]);
+    }
+
+    #[test]
+    fn cuts_the_snippet_around_the_first_match_on_char_boundaries() {
+        let text = format!("{} needle {}", "é".repeat(100), "ü".repeat(100));
+        let found = hit(&message(1, "general", &text), &[String::from("needle")]);
+
+        let expected = format!("…{} needle {}…", "é".repeat(59), "ü".repeat(59));
+        assert_eq!(found.snippet, expected);
+        assert_eq!(highlighted(&found), ["needle"]);
+    }
+}
@@ -0,0 +1,458 @@
+//! Pluggable message storage for the chat service.
+//!
+//! Every message accepted by `send_msg` is written through a [`MessageStore`]
+//! before it is fanned out, so clients that connect later can fetch the past
+//! conversation with `GetHistory`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+use prost::Message;
+
+use crate::proto::{ChatMessage, Room};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("failed to decode stored message: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("storage task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl From<StorageError> for tonic::Status {
+    fn from(err: StorageError) -> Self {
+        tonic::Status::internal(err.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+#[tonic::async_trait]
+pub trait MessageStore: Send + Sync + 'static {
+    /// Persists a message that has been accepted by the chat service. The
+    /// message already carries its server-assigned `id`.
+    async fn append(&self, msg: ChatMessage) -> Result<()>;
+
+    /// Replaces a stored message after it was edited or deleted.
+    async fn update(&self, msg: ChatMessage) -> Result<()>;
+
+    /// Returns the highest message id or revision stored so far, or 0 when
+    /// empty, so ids keep increasing across restarts.
+    async fn last_id(&self) -> Result<u64>;
+
+    /// Returns the message with `id`, if there is one.
+    async fn message(&self, id: u64) -> Result<Option<ChatMessage>>;
+
+    /// Returns up to `limit` top-level messages of `room_id` from the part
+    /// of the history `page` selects, oldest first.
+    async fn history(&self, room_id: &str, page: Page, limit: usize) -> Result<Vec<ChatMessage>>;
+
+    /// Returns up to `limit` of the messages in `room_id` most recently
+    /// posted or changed, i.e. with the highest of `id` and `revision`, in no
+    /// particular order.
+    async fn recent(&self, room_id: &str, limit: usize) -> Result<Vec<ChatMessage>>;
+
+    /// Returns every stored message, oldest first, e.g. to build the search
+    /// index at startup.
+    async fn messages(&self) -> Result<Vec<ChatMessage>>;
+
+    /// Returns every reply to one of `parent_ids`, oldest first.
+    async fn replies(&self, parent_ids: &[u64]) -> Result<Vec<ChatMessage>>;
+
+    /// Returns how many replies each of `parent_ids` had that weren't
+    /// deleted as of seq `as_of`. Parents without any are left out.
+    async fn reply_counts(&self, parent_ids: &[u64], as_of: u64) -> Result<HashMap<u64, u32>> {
+        let mut counts = HashMap::new();
+        for reply in self.replies(parent_ids).await? {
+            // A deleted message can't change again, so its revision is when
+            // it was deleted.
+            let deleted = reply.deleted && reply.revision <= as_of;
+            if reply.id <= as_of && !deleted {
+                *counts.entry(reply.parent_id).or_default() += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Persists a newly created room.
+    async fn save_room(&self, room: Room) -> Result<()>;
+
+    /// Returns every room that has been saved, in creation order.
+    async fn rooms(&self) -> Result<Vec<Room>>;
+
+    /// Fails unless the store can currently serve reads and writes, for
+    /// health checks.
+    async fn ping(&self) -> Result<()>;
+
+    /// Writes out anything still buffered, before the process exits.
+    async fn flush(&self) -> Result<()>;
+}
+
+/// Which top-level messages of a room [`MessageStore::history`] returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Page {
+    /// The most recent ones.
+    Latest,
+    /// The most recent ones older than this id.
+    Before(u64),
+    /// The oldest ones newer than this id.
+    After(u64),
+}
+
+/// Keeps messages in process memory. History is lost when the backend exits.
+#[derive(Default)]
+pub struct MemoryStore {
+    messages: Mutex<Vec<ChatMessage>>,
+    rooms: Mutex<Vec<Room>>,
+}
+
+#[tonic::async_trait]
+impl MessageStore for MemoryStore {
+    async fn append(&self, msg: ChatMessage) -> Result<()> {
+        self.messages.lock().await.push(msg);
+        Ok(())
+    }
+
+    async fn update(&self, msg: ChatMessage) -> Result<()> {
+        let mut messages = self.messages.lock().await;
+        // Appended in id order, so the vector stays sorted by id.
+        if let Ok(idx) = messages.binary_search_by_key(&msg.id, |stored| stored.id) {
+            messages[idx] = msg;
+        }
+        Ok(())
+    }
+
+    async fn last_id(&self) -> Result<u64> {
+        Ok(self
+            .messages
+            .lock()
+            .await
+            .iter()
+            .map(|msg| msg.id.max(msg.revision))
+            .max()
+            .unwrap_or(0))
+    }
+
+    async fn message(&self, id: u64) -> Result<Option<ChatMessage>> {
+        let messages = self.messages.lock().await;
+        Ok(messages
+            .binary_search_by_key(&id, |stored| stored.id)
+            .ok()
+            .map(|idx| messages[idx].clone()))
+    }
+
+    async fn history(&self, room_id: &str, page: Page, limit: usize) -> Result<Vec<ChatMessage>> {
+        let messages = self.messages.lock().await;
+        let shown = |msg: &&ChatMessage| msg.room_id == room_id && msg.parent_id == 0;
+        let history = match page {
+            Page::After(after_id) => {
+                let start = messages.partition_point(|msg| msg.id <= after_id);
+                messages[start..]
+                    .iter()
+                    .filter(shown)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            }
+            Page::Latest | Page::Before(_) => {
+                let end = match page {
+                    Page::Before(before_id) => messages.partition_point(|msg| msg.id < before_id),
+                    _ => messages.len(),
+                };
+                let mut history: Vec<_> = messages[..end]
+                    .iter()
+                    .rev()
+                    .filter(shown)
+                    .take(limit)
+                    .cloned()
+                    .collect();
+                history.reverse();
+                history
+            }
+        };
+        Ok(history)
+    }
+
+    async fn recent(&self, room_id: &str, limit: usize) -> Result<Vec<ChatMessage>> {
+        let mut recent: Vec<_> = self
+            .messages
+            .lock()
+            .await
+            .iter()
+            .filter(|msg| msg.room_id == room_id)
+            .cloned()
+            .collect();
+        recent.sort_by_key(|msg| std::cmp::Reverse(msg.id.max(msg.revision)));
+        recent.truncate(limit);
+        Ok(recent)
+    }
+
+    async fn messages(&self) -> Result<Vec<ChatMessage>> {
+        Ok(self.messages.lock().await.clone())
+    }
+
+    async fn replies(&self, parent_ids: &[u64]) -> Result<Vec<ChatMessage>> {
+        Ok(self
+            .messages
+            .lock()
+            .await
+            .iter()
+            .filter(|msg| msg.parent_id != 0 && parent_ids.contains(&msg.parent_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn save_room(&self, room: Room) -> Result<()> {
+        self.rooms.lock().await.push(room);
+        Ok(())
+    }
+
+    async fn rooms(&self) -> Result<Vec<Room>> {
+        Ok(self.rooms.lock().await.clone())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes an `(id, body)` row. Rows written before ids were assigned by the
+/// chat service have no id in the body, so the row id fills it in.
+fn decode_message(row: rusqlite::Result<(i64, Vec<u8>)>) -> Result<ChatMessage> {
+    let (id, body) = row?;
+    Ok(ChatMessage {
+        id: id as u64,
+        ..ChatMessage::decode(&body[..])?
+    })
+}
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many
+/// have already run against a database file.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS messages (
+        id   INTEGER PRIMARY KEY AUTOINCREMENT,
+        body BLOB NOT NULL
+    );",
+    "ALTER TABLE messages ADD COLUMN room_id TEXT NOT NULL DEFAULT 'general';
+    CREATE INDEX messages_room_id ON messages (room_id, id);
+    CREATE TABLE rooms (
+        id   TEXT PRIMARY KEY,
+        body BLOB NOT NULL
+    );",
+    "ALTER TABLE messages ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE messages ADD COLUMN parent_id INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX messages_parent_id ON messages (parent_id, id);",
+];
+
+/// Stores messages in an embedded SQLite database so history survives
+/// restarts. Messages are kept as encoded protobuf blobs so new fields on
+/// `ChatMessage` don't require a schema migration.
+pub struct SqliteStore {
+    conn: Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut conn = rusqlite::Connection::open(path)?;
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", idx as i64 + 1)?;
+            tx.commit()?;
+        }
+        Ok(Self {
+            conn: Arc::new(std::sync::Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` against the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&rusqlite::Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+#[tonic::async_trait]
+impl MessageStore for SqliteStore {
+    async fn append(&self, msg: ChatMessage) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO messages (id, room_id, parent_id, body) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![
+                    msg.id as i64,
+                    msg.room_id,
+                    msg.parent_id as i64,
+                    msg.encode_to_vec()
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update(&self, msg: ChatMessage) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE messages SET revision = ?2, body = ?3 WHERE id = ?1",
+                rusqlite::params![msg.id as i64, msg.revision as i64, msg.encode_to_vec()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn last_id(&self) -> Result<u64> {
+        self.with_conn(|conn| {
+            let id: i64 = conn.query_row(
+                "SELECT COALESCE(MAX(MAX(id), MAX(revision)), 0) FROM messages",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(id as u64)
+        })
+        .await
+    }
+
+    async fn message(&self, id: u64) -> Result<Option<ChatMessage>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT id, body FROM messages WHERE id = ?1")?;
+            let mut rows = stmt.query_map([id as i64], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.next().map(decode_message).transpose()
+        })
+        .await
+    }
+
+    async fn history(&self, room_id: &str, page: Page, limit: usize) -> Result<Vec<ChatMessage>> {
+        let room_id = room_id.to_owned();
+        let newest_first = "SELECT id, body FROM messages
+            WHERE room_id = ?1 AND parent_id = 0 AND id < ?2 ORDER BY id DESC LIMIT ?3";
+        let (sql, bound) = match page {
+            Page::After(after_id) => (
+                "SELECT id, body FROM messages
+                 WHERE room_id = ?1 AND parent_id = 0 AND id > ?2 ORDER BY id LIMIT ?3",
+                after_id as i64,
+            ),
+            Page::Before(before_id) => (newest_first, before_id as i64),
+            // Ids are stored as i64, so this bound is past every row.
+            Page::Latest => (newest_first, i64::MAX),
+        };
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(sql)?;
+            let mut messages = stmt
+                .query_map(rusqlite::params![room_id, bound, limit as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .map(decode_message)
+                .collect::<Result<Vec<_>>>()?;
+            if !matches!(page, Page::After(_)) {
+                messages.reverse();
+            }
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn recent(&self, room_id: &str, limit: usize) -> Result<Vec<ChatMessage>> {
+        let room_id = room_id.to_owned();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, body FROM messages WHERE room_id = ?1
+                 ORDER BY MAX(id, revision) DESC LIMIT ?2",
+            )?;
+            let messages = stmt
+                .query_map(rusqlite::params![room_id, limit as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .map(decode_message)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn messages(&self) -> Result<Vec<ChatMessage>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT id, body FROM messages ORDER BY id")?;
+            let messages = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .map(decode_message)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn replies(&self, parent_ids: &[u64]) -> Result<Vec<ChatMessage>> {
+        if parent_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let parent_ids: Vec<i64> = parent_ids.iter().map(|id| *id as i64).collect();
+        self.with_conn(move |conn| {
+            let sql = format!(
+                "SELECT id, body FROM messages WHERE parent_id IN ({}) ORDER BY id",
+                vec!["?"; parent_ids.len()].join(", ")
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let replies = stmt
+                .query_map(rusqlite::params_from_iter(parent_ids), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .map(decode_message)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(replies)
+        })
+        .await
+    }
+
+    async fn save_room(&self, room: Room) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO rooms (id, body) VALUES (?1, ?2)",
+                rusqlite::params![room.id, room.encode_to_vec()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rooms(&self) -> Result<Vec<Room>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT body FROM rooms ORDER BY rowid")?;
+            let rooms = stmt
+                .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+                .map(|body| Ok(Room::decode(&body?[..])?))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(rooms)
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM rooms", [], |_| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.with_conn(|conn| Ok(conn.cache_flush()?)).await
+    }
+}
@@ -0,0 +1,30 @@
+//! Logging and distributed tracing, set up by [`chat_common::telemetry`].
+//!
+//! Callers pass their trace context as W3C `traceparent` metadata, which
+//! [`request_span`] picks up so the backend's spans join the caller's trace.
+
+use tracing::Span;
+
+pub use chat_common::telemetry::{Telemetry, TelemetryError};
+
+/// Name the backend's spans are exported under.
+const SERVICE_NAME: &str = "chat-backend";
+
+/// Sets up logging, and exporting the backend's spans, see
+/// [`chat_common::telemetry::init`].
+pub fn init(log_level: &str, otlp_endpoint: Option<&str>) -> Result<Telemetry, TelemetryError> {
+    chat_common::telemetry::init(SERVICE_NAME, log_level, otlp_endpoint)
+}
+
+/// The span a gRPC call is handled in, a child of the caller's span when the
+/// request carries one. `user` is filled in once the session is checked.
+pub fn request_span<B>(request: &http::Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "grpc",
+        otel.name = %request.uri().path(),
+        otel.kind = "server",
+        user = tracing::field::Empty,
+    );
+    chat_common::telemetry::join_trace(&span, request.headers());
+    span
+}
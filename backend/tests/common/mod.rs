@@ -0,0 +1,150 @@
+//! Runs the backend binary for integration tests.
+
+#![allow(dead_code, unused_imports)]
+
+use std::net::SocketAddr;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use backend::proto::chat_service_client::ChatServiceClient;
+use backend::proto::User;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+
+pub use chat_common::testing::free_addr;
+
+/// A client logged in as one user, see [`Backend::join`].
+pub type Client = ChatServiceClient<InterceptedService<Channel, Bearer>>;
+
+/// The backend binary serving on a free local port, killed on drop.
+pub struct Backend {
+    child: Child,
+    pub addr: SocketAddr,
+}
+
+impl Backend {
+    /// Starts the backend with in-memory storage, after `configure` has added
+    /// its own flags and environment.
+    pub fn start(configure: impl FnOnce(&mut Command)) -> Self {
+        let addr = free_addr();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_backend"));
+        command
+            .arg("--listen")
+            .arg(addr.to_string())
+            .env_remove("CHAT_CONFIG")
+            .env_remove("CHAT_DB")
+            .env_remove("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        configure(&mut command);
+        let child = command.spawn().expect("backend starts");
+        Self { child, addr }
+    }
+
+    /// Starts the backend like [`Backend::start`] and waits until it is
+    /// ready.
+    pub async fn started(configure: impl FnOnce(&mut Command)) -> Self {
+        let backend = Self::start(configure);
+        backend.ready().await;
+        backend
+    }
+
+    /// Connects without a session.
+    pub async fn client(&self) -> ChatServiceClient<Channel> {
+        ChatServiceClient::new(self.endpoint().connect().await.unwrap())
+    }
+
+    /// Logs in as `name` and returns a client that sends the session with
+    /// every request.
+    pub async fn join(&self, name: &str) -> Client {
+        let channel = self.endpoint().connect().await.unwrap();
+        let token = join(&mut ChatServiceClient::new(channel.clone()), name).await;
+        assert!(!token.is_empty(), "{} is taken", name);
+        ChatServiceClient::with_interceptor(channel, Bearer::new(&token))
+    }
+
+    /// The backend's address over plain HTTP/2.
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::from_shared(format!("http://{}", self.addr)).unwrap()
+    }
+
+    /// Waits until the backend accepts connections.
+    pub async fn ready(&self) {
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(self.addr).await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("backend didn't start listening on {}", self.addr);
+    }
+
+    /// Sends SIGTERM, as a process manager would to stop the backend.
+    pub fn terminate(&self) {
+        let status = Command::new("kill")
+            .arg("-TERM")
+            .arg(self.child.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Waits up to `timeout` for the backend to exit.
+    pub async fn exited(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        for _ in 0..timeout.as_millis() / 50 {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return Some(status);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        None
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Logs in as `name` and returns the session token, empty if the name is
+/// taken.
+pub async fn join(client: &mut ChatServiceClient<Channel>, name: &str) -> String {
+    let user = User {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    client.join(user).await.unwrap().into_inner().token
+}
+
+/// Wraps `message` in a request carrying the session `token`.
+pub fn authorized<T>(message: T, token: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().unwrap(),
+    );
+    request
+}
+
+/// Adds a session token to every request of a [`Client`].
+#[derive(Clone)]
+pub struct Bearer(MetadataValue<Ascii>);
+
+impl Bearer {
+    pub fn new(token: &str) -> Self {
+        Self(format!("Bearer {}", token).parse().unwrap())
+    }
+}
+
+impl Interceptor for Bearer {
+    fn call(&mut self, mut request: tonic::Request<()>) -> tonic::Result<tonic::Request<()>> {
+        request
+            .metadata_mut()
+            .insert("authorization", self.0.clone());
+        Ok(request)
+    }
+}
@@ -0,0 +1,96 @@
+//! Layering of the backend's settings and the errors for invalid ones.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use backend::config::{Args, Config, ConfigError};
+use clap::Parser;
+
+#[test]
+fn flags_beat_env_beat_file_beat_defaults() {
+    // Only this test reads the environment, the others build `Args` directly.
+    std::env::set_var("CHAT_HISTORY_LIMIT", "20");
+    std::env::set_var("CHAT_SEARCH_LIMIT", "30");
+    let args = Args::try_parse_from(["backend", "--history-limit", "10"]).unwrap();
+    let file: Args = toml::from_str(
+        "history_limit = 40
+         search_limit = 41
+         max_history_limit = 42",
+    )
+    .unwrap();
+
+    let config = Config::from_layers(args, file).unwrap();
+    assert_eq!(config.limits.history, 10);
+    assert_eq!(config.limits.search, 30);
+    assert_eq!(config.limits.max_history, 42);
+    assert_eq!(config.limits.max_search, 100);
+}
+
+#[test]
+fn defaults() {
+    let config = Config::from_layers(Args::default(), Args::default()).unwrap();
+    assert_eq!(config.listen, "[::1]:50051".parse().unwrap());
+    assert!(config.tls.is_none());
+    assert_eq!((config.send_quota.rate, config.send_quota.burst), (1.0, 5));
+    assert_eq!(config.drain_timeout, Duration::from_secs(10));
+}
+
+#[test]
+fn tls_needs_both_cert_and_key() {
+    let cert_only = Args {
+        tls_cert: Some(PathBuf::from("server.pem")),
+        ..Default::default()
+    };
+    let result = Config::from_layers(cert_only, Args::default());
+    assert!(matches!(
+        result,
+        Err(ConfigError::Incomplete("tls_cert", "tls_key"))
+    ));
+
+    // The key may come from another layer than the certificate.
+    let key_in_file = Args {
+        tls_key: Some(PathBuf::from("server.key")),
+        ..Default::default()
+    };
+    let cert_in_args = Args {
+        tls_cert: Some(PathBuf::from("server.pem")),
+        ..Default::default()
+    };
+    let tls = Config::from_layers(cert_in_args, key_in_file)
+        .unwrap()
+        .tls
+        .unwrap();
+    assert_eq!(tls.key, PathBuf::from("server.key"));
+}
+
+#[test]
+fn send_quota_must_be_positive() {
+    for (args, setting) in [
+        (
+            Args {
+                send_rate: Some(0.0),
+                ..Default::default()
+            },
+            "send_rate",
+        ),
+        (
+            Args {
+                send_rate: Some(f64::NAN),
+                ..Default::default()
+            },
+            "send_rate",
+        ),
+        (
+            Args {
+                send_burst: Some(0),
+                ..Default::default()
+            },
+            "send_burst",
+        ),
+    ] {
+        match Config::from_layers(args, Args::default()) {
+            Err(ConfigError::NotPositive(name)) => assert_eq!(name, setting),
+            other => panic!("expected {} to be refused, got {:?}", setting, other),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! The standard health and reflection services next to the chat service.
+
+mod common;
+
+use backend::proto::chat_service_server::SERVICE_NAME;
+use tokio_stream::StreamExt;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+use common::Backend;
+
+#[tokio::test]
+async fn reports_serving_with_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = Backend::started(|command| {
+        command.env("CHAT_DB", dir.path().join("chat.db"));
+    })
+    .await;
+    let mut client = HealthClient::new(backend.endpoint().connect().await.unwrap());
+
+    for service in ["", SERVICE_NAME] {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let response = client.check(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), ServingStatus::Serving, "{:?}", service);
+    }
+    let unknown = HealthCheckRequest {
+        service: String::from("chat.Missing"),
+    };
+    let status = client.check(unknown).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn lists_services_by_reflection() {
+    let backend = Backend::started(|_| {}).await;
+    let mut client = ServerReflectionClient::new(backend.endpoint().connect().await.unwrap());
+
+    let request = ServerReflectionRequest {
+        message_request: Some(MessageRequest::ListServices(String::new())),
+        ..Default::default()
+    };
+    let mut responses = client
+        .server_reflection_info(tokio_stream::iter([request]))
+        .await
+        .unwrap()
+        .into_inner();
+    let response = responses.next().await.unwrap().unwrap();
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!("unexpected response: {:?}", response.message_response);
+    };
+    let mut services: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+    services.sort();
+    assert_eq!(
+        services,
+        [
+            SERVICE_NAME,
+            "grpc.health.v1.Health",
+            "grpc.reflection.v1.ServerReflection"
+        ]
+    );
+}
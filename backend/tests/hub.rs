@@ -0,0 +1,34 @@
+//! Fan-out of room traffic to subscribers that keep up and ones that don't.
+
+use backend::hub::Hub;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn disconnects_only_the_subscriber_that_lags() {
+    let hub = Hub::new(4);
+    let mut slow = hub.subscribe();
+    let mut fast = hub.subscribe();
+
+    for n in 0..10 {
+        assert_eq!(hub.publish(n), 2);
+        assert_eq!(fast.next().await.unwrap().unwrap(), n);
+    }
+
+    let status = slow.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert!(slow.next().await.is_none());
+    // Nothing more arrives once the subscriber is told it lagged.
+    hub.publish(10);
+    assert!(slow.next().await.is_none());
+    assert_eq!(fast.next().await.unwrap().unwrap(), 10);
+}
+
+#[tokio::test]
+async fn counts_subscribers_until_they_are_dropped() {
+    let hub = Hub::<u32>::default();
+    assert_eq!(hub.publish(0), 0);
+    let subscription = hub.subscribe();
+    assert_eq!(hub.subscribers(), 1);
+    drop(subscription);
+    assert_eq!(hub.subscribers(), 0);
+}
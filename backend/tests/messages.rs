@@ -0,0 +1,563 @@
+//! Sending messages to the backend and receiving them back.
+
+mod common;
+
+use std::time::Duration;
+
+use backend::proto::chat_event::Event;
+use backend::proto::{
+    direct_room_id, ChatMessage, CreateRoomRequest, DeleteMessageRequest, DirectMessage,
+    DirectRoomRequest, EditMessageRequest, Empty, History, HistoryRequest, ReactionRequest,
+    RecieveMsgRequest, RoomMembership, SearchRequest, ThreadRequest,
+};
+use tokio_stream::StreamExt;
+
+use common::{authorized, Backend, Client};
+
+#[tokio::test]
+async fn rejects_empty_and_overlong_messages() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    backend.join("bob").await;
+
+    for text in [String::from("  \n "), "x".repeat(1001)] {
+        let message = ChatMessage {
+            msg: text.clone(),
+            ..Default::default()
+        };
+        let status = alice.send_msg(message).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let direct = DirectMessage {
+            to: String::from("bob"),
+            msg: text,
+            ..Default::default()
+        };
+        let status = alice.send_direct(direct).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    let message = ChatMessage {
+        msg: "x".repeat(1000),
+        ..Default::default()
+    };
+    alice.send_msg(message).await.unwrap();
+}
+
+#[tokio::test]
+async fn replays_from_the_start_after_zero() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+
+    // Posted after a page loaded the empty room, before its stream opened.
+    let message = ChatMessage {
+        msg: String::from("first"),
+        ..Default::default()
+    };
+    alice.send_msg(message).await.unwrap();
+
+    let request = RecieveMsgRequest {
+        after_id: Some(0),
+        ..Default::default()
+    };
+    let mut events = alice.recieve_msg(request).await.unwrap().into_inner();
+    let event = events.next().await.unwrap().unwrap();
+    match event.event {
+        Some(Event::NewMessage(msg)) => assert_eq!(msg.msg, "first"),
+        other => panic!("expected the first message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn resumes_old_edits_across_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("chat.db");
+    let start = || {
+        Backend::started(|command| {
+            command.env("CHAT_DB", &db);
+            command.args(["--send-rate", "100000", "--send-burst", "100000"]);
+        })
+    };
+
+    let backend = start().await;
+    let mut alice = backend.join("alice").await;
+    // More messages than the replay buffer holds, then an edit to the first.
+    for n in 0..1100 {
+        let message = ChatMessage {
+            msg: format!("message {}", n),
+            ..Default::default()
+        };
+        alice.send_msg(message).await.unwrap();
+    }
+    let edit = EditMessageRequest {
+        id: 1,
+        msg: String::from("edited"),
+    };
+    alice.edit_message(edit).await.unwrap();
+    drop(backend);
+
+    let backend = start().await;
+    let mut alice = backend.join("alice").await;
+    let request = RecieveMsgRequest {
+        after_id: Some(1100),
+        ..Default::default()
+    };
+    let mut events = alice.recieve_msg(request).await.unwrap().into_inner();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("the edit is replayed")
+        .unwrap()
+        .unwrap();
+    match event.event {
+        Some(Event::Edited(msg)) => assert_eq!((msg.id, msg.msg.as_str()), (1, "edited")),
+        other => panic!("expected the edit, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn pages_history_on_top_level_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("chat.db");
+    for sqlite in [false, true] {
+        let backend = Backend::started(|command| {
+            command.args(["--send-rate", "100", "--send-burst", "100"]);
+            if sqlite {
+                command.env("CHAT_DB", &db);
+            }
+        })
+        .await;
+        let mut alice = backend.join("alice").await;
+        // Three messages with two replies each.
+        let mut ids = Vec::new();
+        for n in 0..3 {
+            let message = ChatMessage {
+                msg: format!("message {}", n),
+                ..Default::default()
+            };
+            alice.send_msg(message).await.unwrap();
+            let request = HistoryRequest {
+                limit: 1,
+                ..Default::default()
+            };
+            let latest = alice.get_history(request).await.unwrap().into_inner();
+            let id = latest.messages[0].id;
+            ids.push(id);
+            for r in 0..2 {
+                let reply = ChatMessage {
+                    msg: format!("reply {}.{}", n, r),
+                    parent_id: id,
+                    ..Default::default()
+                };
+                alice.send_msg(reply).await.unwrap();
+            }
+        }
+
+        // A deleted reply no longer counts.
+        let request = ThreadRequest { message_id: ids[2] };
+        let thread = alice.get_thread(request).await.unwrap().into_inner();
+        let request = DeleteMessageRequest {
+            id: thread.replies[0].id,
+        };
+        alice.delete_message(request).await.unwrap();
+
+        let history = |before_id, after_id| {
+            let request = HistoryRequest {
+                limit: 2,
+                before_id,
+                after_id,
+                ..Default::default()
+            };
+            let mut alice = alice.clone();
+            async move {
+                let History {
+                    messages,
+                    has_more,
+                    reply_counts,
+                    last_seq,
+                } = alice.get_history(request).await.unwrap().into_inner();
+                assert_eq!(last_seq, 10);
+                let counts = messages
+                    .iter()
+                    .map(|msg| reply_counts.get(&msg.id).copied().unwrap_or(0))
+                    .collect::<Vec<_>>();
+                let texts = messages.into_iter().map(|msg| msg.msg).collect::<Vec<_>>();
+                (texts, has_more, counts)
+            }
+        };
+
+        let (messages, has_more, counts) = history(0, 0).await;
+        assert_eq!(messages, ["message 1", "message 2"]);
+        assert!(has_more);
+        assert_eq!(counts, [2, 1]);
+
+        let (messages, has_more, counts) = history(ids[1], 0).await;
+        assert_eq!(messages, ["message 0"]);
+        assert!(!has_more);
+        assert_eq!(counts, [2]);
+
+        let (messages, has_more, _) = history(0, ids[0]).await;
+        assert_eq!(messages, ["message 1", "message 2"]);
+        assert!(!has_more);
+        drop(backend);
+    }
+}
+
+/// Opens the direct conversation of `client` with `user` and subscribes to it.
+async fn direct_room(
+    client: &mut common::Client,
+    user: &str,
+) -> (String, tonic::Streaming<backend::proto::ChatEvent>) {
+    let request = DirectRoomRequest {
+        user: user.to_string(),
+    };
+    let room_id = client
+        .get_direct_room(request)
+        .await
+        .unwrap()
+        .into_inner()
+        .id;
+    let request = RecieveMsgRequest {
+        room_id: room_id.clone(),
+        ..Default::default()
+    };
+    let events = client.recieve_msg(request).await.unwrap().into_inner();
+    (room_id, events)
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_their_participants() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+    let mut carol = backend.join("carol").await;
+
+    let (room_id, mut alices) = direct_room(&mut alice, "bob").await;
+    let (bobs_room_id, mut bobs) = direct_room(&mut bob, "alice").await;
+    assert_eq!(room_id, bobs_room_id);
+
+    let request = RecieveMsgRequest {
+        room_id: room_id.clone(),
+        ..Default::default()
+    };
+    let status = carol.recieve_msg(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    let direct = DirectMessage {
+        to: String::from("bob"),
+        msg: String::from("just between us"),
+        ..Default::default()
+    };
+    alice.send_direct(direct).await.unwrap();
+    for events in [&mut alices, &mut bobs] {
+        match events.next().await.unwrap().unwrap().event {
+            Some(Event::NewMessage(msg)) => {
+                assert_eq!(msg.msg, "just between us");
+                assert_eq!((msg.from.as_str(), msg.to.as_str()), ("alice", "bob"));
+            }
+            other => panic!("expected the direct message, got {:?}", other),
+        }
+    }
+
+    let request = HistoryRequest {
+        room_id: room_id.clone(),
+        ..Default::default()
+    };
+    let status = carol.get_history(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let request = SearchRequest {
+        query: String::from("between"),
+        ..Default::default()
+    };
+    let found = carol.search_messages(request).await.unwrap().into_inner();
+    assert!(found.hits.is_empty());
+}
+
+#[tokio::test]
+async fn direct_messages_stay_with_the_user_not_the_name() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+
+    let (room_id, _events) = direct_room(&mut alice, "bob").await;
+    let direct = DirectMessage {
+        to: String::from("bob"),
+        msg: String::from("just between us"),
+        ..Default::default()
+    };
+    alice.send_direct(direct).await.unwrap();
+    alice.leave(Empty {}).await.unwrap();
+
+    // Whoever takes the name next starts a new conversation.
+    let mut next_alice = backend.join("alice").await;
+    let (next_room_id, _events) = direct_room(&mut next_alice, "bob").await;
+    assert_ne!(next_room_id, room_id);
+    let request = HistoryRequest {
+        room_id: room_id.clone(),
+        ..Default::default()
+    };
+    let status = next_alice.get_history(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    // Bob still has the old conversation.
+    let request = HistoryRequest {
+        room_id,
+        ..Default::default()
+    };
+    let history = bob.get_history(request).await.unwrap().into_inner();
+    assert_eq!(history.messages.len(), 1);
+}
+
+#[tokio::test]
+async fn refuses_direct_rooms_with_absent_users() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+
+    let request = DirectRoomRequest {
+        user: String::from("nobody"),
+    };
+    let status = alice.get_direct_room(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let users = alice.get_all_users(Empty {}).await.unwrap().into_inner();
+    let request = RecieveMsgRequest {
+        room_id: direct_room_id(&users.users[0].id, "made-up"),
+        ..Default::default()
+    };
+    let status = alice.recieve_msg(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn refuses_calls_without_a_valid_session() {
+    let backend = Backend::started(|_| {}).await;
+    let mut client = backend.client().await;
+    let message = || ChatMessage {
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+
+    let status = client.send_msg(message()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let request = authorized(message(), "made-up");
+    let status = client.send_msg(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn refuses_a_session_after_leave() {
+    let backend = Backend::started(|_| {}).await;
+    let mut client = backend.client().await;
+    let token = common::join(&mut client, "alice").await;
+    let message = || ChatMessage {
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+    client
+        .send_msg(authorized(message(), &token))
+        .await
+        .unwrap();
+
+    client.leave(authorized(Empty {}, &token)).await.unwrap();
+    let status = client
+        .send_msg(authorized(message(), &token))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let status = client
+        .leave(authorized(Empty {}, &token))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn sends_as_the_sessions_user() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    backend.join("bob").await;
+
+    let message = ChatMessage {
+        from: String::from("bob"),
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+    alice.send_msg(message).await.unwrap();
+
+    let history = alice
+        .get_history(HistoryRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(history.messages[0].from, "alice");
+}
+
+/// Posts `text` to `room_id` and returns the new message's id.
+async fn post(client: &mut Client, room_id: &str, text: &str) -> u64 {
+    let message = ChatMessage {
+        msg: text.to_string(),
+        room_id: room_id.to_string(),
+        ..Default::default()
+    };
+    client.send_msg(message).await.unwrap();
+    let request = HistoryRequest {
+        room_id: room_id.to_string(),
+        limit: 1,
+        ..Default::default()
+    };
+    let history = client.get_history(request).await.unwrap().into_inner();
+    history.messages[0].id
+}
+
+#[tokio::test]
+async fn deletes_for_the_author_and_the_room_owner_only() {
+    let backend = Backend::started(|command| {
+        command.args(["--send-rate", "100", "--send-burst", "100"]);
+    })
+    .await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+    let mut carol = backend.join("carol").await;
+    let request = CreateRoomRequest {
+        name: String::from("club"),
+    };
+    alice.create_room(request).await.unwrap();
+    for client in [&mut alice, &mut bob, &mut carol] {
+        let membership = RoomMembership {
+            room_id: String::from("club"),
+        };
+        client.join_room(membership).await.unwrap();
+    }
+    let first = post(&mut bob, "club", "first").await;
+    let second = post(&mut bob, "club", "second").await;
+    let reaction = ReactionRequest {
+        message_id: first,
+        emoji: String::from("👍"),
+    };
+    carol.add_reaction(reaction).await.unwrap();
+    let request = RecieveMsgRequest {
+        room_id: String::from("club"),
+        ..Default::default()
+    };
+    let mut events = carol.recieve_msg(request).await.unwrap().into_inner();
+
+    let delete = |id| DeleteMessageRequest { id };
+    let status = carol.delete_message(delete(first)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    alice.delete_message(delete(first)).await.unwrap();
+    bob.delete_message(delete(second)).await.unwrap();
+
+    for id in [first, second] {
+        let tombstone = match events.next().await.unwrap().unwrap().event {
+            Some(Event::Deleted(msg)) => msg,
+            other => panic!("expected the deletion, got {:?}", other),
+        };
+        assert_eq!(tombstone.id, id);
+        assert!(tombstone.deleted);
+        assert!(tombstone.msg.is_empty());
+        assert!(tombstone.reactions.is_empty());
+        assert_eq!(tombstone.from, "bob");
+    }
+    let request = HistoryRequest {
+        room_id: String::from("club"),
+        ..Default::default()
+    };
+    let history = bob.get_history(request).await.unwrap().into_inner();
+    assert!(history
+        .messages
+        .iter()
+        .all(|msg| msg.deleted && msg.msg.is_empty()));
+
+    // Tombstones stay deleted.
+    let edit = EditMessageRequest {
+        id: first,
+        msg: String::from("back again"),
+    };
+    let status = bob.edit_message(edit).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn reacts_as_a_room_member_once_per_emoji() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+    let request = CreateRoomRequest {
+        name: String::from("club"),
+    };
+    alice.create_room(request).await.unwrap();
+    let membership = || RoomMembership {
+        room_id: String::from("club"),
+    };
+    alice.join_room(membership()).await.unwrap();
+    let id = post(&mut alice, "club", "hello").await;
+    let reaction = || ReactionRequest {
+        message_id: id,
+        emoji: String::from("👍"),
+    };
+
+    let status = bob.add_reaction(reaction()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    bob.join_room(membership()).await.unwrap();
+    bob.add_reaction(reaction()).await.unwrap();
+    bob.add_reaction(reaction()).await.unwrap();
+    alice.add_reaction(reaction()).await.unwrap();
+
+    let reader = alice.clone();
+    let reactions = || {
+        let mut reader = reader.clone();
+        async move {
+            let request = HistoryRequest {
+                room_id: String::from("club"),
+                ..Default::default()
+            };
+            let history = reader.get_history(request).await.unwrap().into_inner();
+            history.messages[0].reactions.clone()
+        }
+    };
+    let counted = reactions().await;
+    assert_eq!(counted.len(), 1);
+    assert_eq!(counted[0].users, ["bob", "alice"]);
+
+    bob.remove_reaction(reaction()).await.unwrap();
+    alice.remove_reaction(reaction()).await.unwrap();
+    assert!(reactions().await.is_empty());
+}
+
+#[tokio::test]
+async fn replies_only_to_top_level_messages_in_the_same_room() {
+    let backend = Backend::started(|command| {
+        command.args(["--send-rate", "100", "--send-burst", "100"]);
+    })
+    .await;
+    let mut alice = backend.join("alice").await;
+    let request = CreateRoomRequest {
+        name: String::from("club"),
+    };
+    alice.create_room(request).await.unwrap();
+    let membership = RoomMembership {
+        room_id: String::from("club"),
+    };
+    alice.join_room(membership).await.unwrap();
+    let parent = post(&mut alice, "general", "parent").await;
+    let elsewhere = post(&mut alice, "club", "elsewhere").await;
+
+    let reply = |parent_id| ChatMessage {
+        msg: String::from("reply"),
+        parent_id,
+        ..Default::default()
+    };
+    alice.send_msg(reply(parent)).await.unwrap();
+    let thread = alice
+        .get_thread(ThreadRequest { message_id: parent })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(thread.replies.len(), 1);
+    let nested = thread.replies[0].id;
+
+    // A reply to a reply, to a message in another room, and to nothing.
+    for parent_id in [nested, elsewhere, 9999] {
+        let status = alice.send_msg(reply(parent_id)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument, "{}", parent_id);
+    }
+}
@@ -0,0 +1,143 @@
+//! The metrics scraped from `/metrics` on the gRPC port.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use backend::hub::Hub;
+use backend::metrics::{Metrics, Observed};
+use backend::proto::{ChatEvent, ChatMessage, RecieveMsgRequest};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+use common::{authorized, join, Backend};
+
+/// Fetches `/metrics` over HTTP/1.1 and returns every sample by series, e.g.
+/// `chat_join_failures_total{reason="name_taken"}`.
+async fn scrape(backend: &Backend) -> BTreeMap<String, f64> {
+    let mut stream = tokio::net::TcpStream::connect(backend.addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: backend\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+    body.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(' '))
+        .map(|(series, value)| (series.to_string(), value.parse().unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn records_chat_traffic() {
+    let backend = Backend::started(|_| {}).await;
+    let mut client = backend.client().await;
+
+    let token = join(&mut client, "alice").await;
+    assert!(join(&mut client, "alice").await.is_empty());
+    let mut events = client
+        .recieve_msg(authorized(RecieveMsgRequest::default(), &token))
+        .await
+        .unwrap()
+        .into_inner();
+    let message = ChatMessage {
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+    client
+        .send_msg(authorized(message.clone(), &token))
+        .await
+        .unwrap();
+    assert!(client.send_msg(message).await.is_err());
+    events.next().await.unwrap().unwrap();
+
+    let metrics = scrape(&backend).await;
+    let sample = |series: &str| metrics.get(series).copied();
+    assert_eq!(sample("chat_messages_total"), Some(1.0));
+    assert_eq!(sample("chat_subscribers"), Some(1.0));
+    assert_eq!(sample("chat_fanout_seconds_count"), Some(1.0));
+    assert_eq!(sample("chat_lagged_subscribers_total"), Some(0.0));
+    assert_eq!(
+        sample(r#"chat_join_failures_total{reason="name_taken"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(r#"grpc_server_handled_total{code="Ok",method="/chat.ChatService/Join"}"#),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(r#"grpc_server_handled_total{code="Ok",method="/chat.ChatService/SendMsg"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            r#"grpc_server_handled_total{code="Unauthenticated",method="/chat.ChatService/SendMsg"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(r#"grpc_server_handling_seconds_count{method="/chat.ChatService/SendMsg"}"#),
+        Some(2.0)
+    );
+
+    // The stream counts until the client drops it.
+    drop(events);
+    for _ in 0..50 {
+        let metrics = scrape(&backend).await;
+        if metrics.get("chat_subscribers") == Some(&0.0) {
+            let cancelled = r#"grpc_server_handled_total{code="Cancelled",method="/chat.ChatService/RecieveMsg"}"#;
+            assert_eq!(metrics.get(cancelled), Some(&1.0));
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("subscriber still counted after the stream was dropped");
+}
+
+#[tokio::test]
+async fn counts_lagged_subscribers() {
+    let metrics = Metrics::new();
+    let hub = Hub::new(2);
+    let mut subscription = Observed::new(hub.subscribe(), metrics.clone());
+    assert_eq!(metrics.subscribers.get(), 1);
+
+    for seq in 1..=5 {
+        hub.publish(ChatEvent { seq, event: None });
+    }
+    let status = subscription.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(metrics.lagged.get(), 1);
+
+    drop(subscription);
+    assert_eq!(metrics.subscribers.get(), 0);
+}
+
+#[tokio::test]
+async fn labels_unknown_paths_as_one_method() {
+    let backend = Backend::started(|_| {}).await;
+    for path in ["/made.Up/Path", "/chat.ChatService/MadeUp"] {
+        let mut stream = tokio::net::TcpStream::connect(backend.addr).await.unwrap();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: backend\r\ncontent-type: application/grpc\r\ncontent-length: 0\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.read_to_end(&mut Vec::new()).await.unwrap();
+    }
+
+    let metrics = scrape(&backend).await;
+    let handled: Vec<_> = metrics
+        .iter()
+        .filter(|(series, _)| series.starts_with("grpc_server_handled_total"))
+        .collect();
+    assert!(handled
+        .iter()
+        .all(|(series, _)| series.contains(r#"method="unknown""#)));
+    let calls: f64 = handled.iter().map(|(_, count)| **count).sum();
+    assert_eq!(calls, 2.0);
+}
@@ -0,0 +1,41 @@
+//! Logging in and out, and the streams that belong to a session.
+
+mod common;
+
+use std::time::Duration;
+
+use backend::proto::{Empty, RecieveMsgRequest};
+use tokio_stream::{Stream, StreamExt};
+
+use common::Backend;
+
+/// Waits for `stream` to end, skipping whatever it still yields.
+async fn ended<T>(stream: impl Stream<Item = T>) {
+    let drained = stream.collect::<Vec<_>>();
+    tokio::time::timeout(Duration::from_secs(5), drained)
+        .await
+        .expect("the stream ends");
+}
+
+#[tokio::test]
+async fn leaving_ends_the_sessions_streams() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+
+    let events = alice
+        .recieve_msg(RecieveMsgRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let presence = alice.watch_presence(Empty {}).await.unwrap().into_inner();
+    let mut watching = bob.watch_presence(Empty {}).await.unwrap().into_inner();
+
+    alice.leave(Empty {}).await.unwrap();
+
+    ended(events).await;
+    ended(presence).await;
+    // Other users' streams carry on.
+    let next = tokio::time::timeout(Duration::from_secs(5), watching.next()).await;
+    assert!(next.is_ok_and(|event| event.is_some_and(|event| event.is_ok())));
+}
@@ -0,0 +1,62 @@
+//! Per-user limits on sending messages and typing updates.
+
+mod common;
+
+use backend::proto::{ChatMessage, SetTypingRequest};
+
+use common::{Backend, Client};
+
+async fn send(client: &mut Client) -> tonic::Result<()> {
+    let message = ChatMessage {
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+    client.send_msg(message).await.map(drop)
+}
+
+#[tokio::test]
+async fn refuses_sends_beyond_quota() {
+    let backend = Backend::started(|command| {
+        command.args(["--send-rate", "0.5", "--send-burst", "2"]);
+    })
+    .await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+
+    send(&mut alice).await.unwrap();
+    send(&mut alice).await.unwrap();
+    let status = send(&mut alice).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(
+        status.message(),
+        "Sending too fast, try again in 2 seconds."
+    );
+    assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+    send(&mut bob).await.unwrap();
+}
+
+#[tokio::test]
+async fn limits_typing_apart_from_sends() {
+    let backend = Backend::started(|command| {
+        command.args(["--send-rate", "0.5", "--send-burst", "2"]);
+    })
+    .await;
+    let mut alice = backend.join("alice").await;
+
+    for typing in [true, false] {
+        let request = SetTypingRequest {
+            typing,
+            ..Default::default()
+        };
+        alice.set_typing(request).await.unwrap();
+    }
+    let request = SetTypingRequest {
+        typing: true,
+        ..Default::default()
+    };
+    let status = alice.set_typing(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+    send(&mut alice).await.unwrap();
+}
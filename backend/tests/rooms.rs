@@ -0,0 +1,84 @@
+//! Creating rooms and joining or leaving them.
+
+mod common;
+
+use backend::proto::{ChatMessage, CreateRoomRequest, Empty, RoomMembership, SetTypingRequest};
+
+use common::{Backend, Client};
+
+async fn send(client: &mut Client, room_id: &str) -> tonic::Result<()> {
+    let message = ChatMessage {
+        msg: String::from("hello"),
+        room_id: room_id.to_string(),
+        ..Default::default()
+    };
+    client.send_msg(message).await.map(drop)
+}
+
+fn membership(room_id: &str) -> RoomMembership {
+    RoomMembership {
+        room_id: room_id.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn only_members_post_in_a_room() {
+    let backend = Backend::started(|command| {
+        command.args(["--send-rate", "100", "--send-burst", "100"]);
+    })
+    .await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+
+    let request = CreateRoomRequest {
+        name: String::from("Rust Talk!"),
+    };
+    let room = alice.create_room(request).await.unwrap().into_inner();
+    assert_eq!(
+        (room.id.as_str(), room.owner.as_str()),
+        ("rust-talk", "alice")
+    );
+    let rooms = bob.list_rooms(Empty {}).await.unwrap().into_inner().rooms;
+    assert!(rooms.contains(&room));
+
+    // Everyone starts out in the default room only.
+    send(&mut bob, "").await.unwrap();
+    let status = send(&mut bob, "rust-talk").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let request = SetTypingRequest {
+        room_id: room.id.clone(),
+        typing: true,
+    };
+    let status = bob.set_typing(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    bob.join_room(membership("rust-talk")).await.unwrap();
+    send(&mut bob, "rust-talk").await.unwrap();
+
+    bob.leave_room(membership("rust-talk")).await.unwrap();
+    let status = send(&mut bob, "rust-talk").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn refuses_unknown_and_duplicate_rooms() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+
+    let status = alice.join_room(membership("nowhere")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    let status = send(&mut alice, "nowhere").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    for (name, code) in [
+        ("Rust", None),
+        ("rust", Some(tonic::Code::AlreadyExists)),
+        ("!!!", Some(tonic::Code::InvalidArgument)),
+    ] {
+        let request = CreateRoomRequest {
+            name: name.to_string(),
+        };
+        let result = alice.create_room(request).await;
+        assert_eq!(result.err().map(|status| status.code()), code, "{}", name);
+    }
+}
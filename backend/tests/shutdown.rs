@@ -0,0 +1,43 @@
+//! Draining event streams when the backend is asked to stop.
+
+mod common;
+
+use std::time::Duration;
+
+use backend::proto::chat_event::Event;
+use backend::proto::{Empty, RecieveMsgRequest};
+use tokio_stream::StreamExt;
+
+use common::Backend;
+
+#[tokio::test]
+async fn sigterm_ends_streams_and_exits() {
+    let mut backend = Backend::started(|_| {}).await;
+    let mut client = backend.join("alice").await;
+
+    let mut events = client
+        .recieve_msg(RecieveMsgRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut presence = client.watch_presence(Empty {}).await.unwrap().into_inner();
+    // The snapshot, then alice coming online.
+    presence.next().await.unwrap().unwrap();
+
+    backend.terminate();
+
+    let last = events.next().await.unwrap().unwrap();
+    assert!(matches!(last.event, Some(Event::Shutdown(_))), "{:?}", last);
+    assert_eq!(last.seq, 0);
+    assert!(events.next().await.is_none());
+    while let Some(event) = presence.next().await {
+        event.unwrap();
+    }
+
+    let status = backend.exited(Duration::from_secs(5)).await;
+    assert!(
+        status.is_some_and(|status| status.success()),
+        "{:?}",
+        status
+    );
+}
@@ -0,0 +1,148 @@
+//! TLS and mutual TLS between a client and the backend binary, with
+//! certificates generated for each test.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use backend::proto::chat_service_client::ChatServiceClient;
+use backend::proto::Empty;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use common::Backend;
+
+/// A certificate authority that signs the server and client certificates.
+struct Ca {
+    issuer: Issuer<'static, KeyPair>,
+    pem: String,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let pem = params.self_signed(&key).unwrap().pem();
+        Self {
+            issuer: Issuer::new(params, key),
+            pem,
+        }
+    }
+
+    /// Returns a certificate for `name` and its key, both PEM encoded.
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()])
+            .unwrap()
+            .signed_by(&key, &self.issuer)
+            .unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+}
+
+fn start(dir: &Path, ca: &Ca, client_ca: Option<&Ca>) -> Backend {
+    let (cert, key) = ca.issue("localhost");
+    Backend::start(|command| {
+        command
+            .arg("--tls-cert")
+            .arg(write(dir, "server.pem", &cert))
+            .arg("--tls-key")
+            .arg(write(dir, "server.key", &key));
+        if let Some(client_ca) = client_ca {
+            command
+                .arg("--tls-client-ca")
+                .arg(write(dir, "client-ca.pem", &client_ca.pem));
+        }
+    })
+}
+
+fn https(backend: &Backend) -> Endpoint {
+    Endpoint::from_shared(format!("https://{}", backend.addr)).unwrap()
+}
+
+fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn tls(ca: &Ca) -> ClientTlsConfig {
+    ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(&ca.pem))
+        .domain_name("localhost")
+}
+
+/// Connects with `config` and makes a call, which is when the handshake
+/// has to have succeeded.
+async fn call(endpoint: Endpoint, config: ClientTlsConfig) -> Result<(), String> {
+    let channel: Channel = endpoint
+        .tls_config(config)
+        .map_err(|e| e.to_string())?
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+    ChatServiceClient::new(channel)
+        .list_rooms(Empty {})
+        .await
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+#[tokio::test]
+async fn serves_tls() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = Ca::new();
+    let backend = start(dir.path(), &ca, None);
+    backend.ready().await;
+
+    call(https(&backend), tls(&ca)).await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_untrusted_server() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = start(dir.path(), &Ca::new(), None);
+    backend.ready().await;
+
+    // Trusting another CA than the one that issued the server certificate.
+    assert!(call(https(&backend), tls(&Ca::new())).await.is_err());
+}
+
+#[tokio::test]
+async fn rejects_plaintext_client() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = start(dir.path(), &Ca::new(), None);
+    backend.ready().await;
+
+    let result = match backend.endpoint().connect().await {
+        Ok(channel) => ChatServiceClient::new(channel)
+            .list_rooms(Empty {})
+            .await
+            .map(drop)
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_client_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = Ca::new();
+    let client_ca = Ca::new();
+    let backend = start(dir.path(), &ca, Some(&client_ca));
+    backend.ready().await;
+
+    // No client certificate.
+    assert!(call(https(&backend), tls(&ca)).await.is_err());
+
+    // A certificate from a CA the backend doesn't trust.
+    let (cert, key) = Ca::new().issue("frontend");
+    let untrusted = tls(&ca).identity(Identity::from_pem(cert, key));
+    assert!(call(https(&backend), untrusted).await.is_err());
+
+    let (cert, key) = client_ca.issue("frontend");
+    let trusted = tls(&ca).identity(Identity::from_pem(cert, key));
+    call(https(&backend), trusted).await.unwrap();
+}
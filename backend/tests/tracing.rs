@@ -0,0 +1,126 @@
+//! Spans exported over OTLP, checked with a collector running in the test.
+
+mod common;
+
+use std::time::Duration;
+
+use backend::proto::Empty;
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+    TraceService, TraceServiceServer,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::trace::v1::span::SpanKind;
+use opentelemetry_proto::tonic::trace::v1::Span;
+use tokio::sync::mpsc;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+
+use common::Backend;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+/// Forwards every export to the test.
+struct Collector(mpsc::UnboundedSender<ExportTraceServiceRequest>);
+
+#[tonic::async_trait]
+impl TraceService for Collector {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportTraceServiceRequest>,
+    ) -> tonic::Result<tonic::Response<ExportTraceServiceResponse>> {
+        let _ = self.0.send(request.into_inner());
+        Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+    }
+}
+
+/// Starts a collector, returning its URL and the exports it receives.
+async fn collector() -> (String, mpsc::UnboundedReceiver<ExportTraceServiceRequest>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, exports) = mpsc::unbounded_channel();
+    tokio::spawn(
+        Server::builder()
+            .add_service(TraceServiceServer::new(Collector(sender)))
+            .serve_with_incoming(TcpIncoming::from(listener)),
+    );
+    (url, exports)
+}
+
+/// Waits for the span named `name`, returning it with its service name.
+async fn exported(
+    exports: &mut mpsc::UnboundedReceiver<ExportTraceServiceRequest>,
+    name: &str,
+) -> (Span, String) {
+    let wait = async {
+        while let Some(export) = exports.recv().await {
+            for resource_spans in export.resource_spans {
+                let service = resource_spans
+                    .resource
+                    .iter()
+                    .flat_map(|resource| &resource.attributes)
+                    .find(|attribute| attribute.key == "service.name")
+                    .and_then(|attribute| attribute.value.as_ref()?.value.as_ref())
+                    .map(|value| match value {
+                        Value::StringValue(name) => name.clone(),
+                        other => format!("{:?}", other),
+                    })
+                    .unwrap_or_default();
+                let spans = resource_spans.scope_spans.into_iter().flat_map(|s| s.spans);
+                if let Some(span) = spans.into_iter().find(|span| span.name == name) {
+                    return (span, service);
+                }
+            }
+        }
+        panic!("collector stopped");
+    };
+    tokio::time::timeout(Duration::from_secs(10), wait)
+        .await
+        .unwrap_or_else(|_| panic!("no {} span exported", name))
+}
+
+#[tokio::test]
+async fn call_joins_the_callers_trace() {
+    let (collector, mut exports) = collector().await;
+    let backend = Backend::started(|command| {
+        command
+            .arg("--otlp-endpoint")
+            .arg(collector)
+            .env("OTEL_BSP_SCHEDULE_DELAY", "50");
+    })
+    .await;
+
+    let mut request = tonic::Request::new(Empty {});
+    let traceparent = format!("00-{}-{}-01", TRACE_ID, PARENT_ID);
+    request
+        .metadata_mut()
+        .insert("traceparent", traceparent.parse().unwrap());
+    backend.client().await.list_rooms(request).await.unwrap();
+
+    let (span, service) = exported(&mut exports, "/chat.ChatService/ListRooms").await;
+    assert_eq!(service, "chat-backend");
+    assert_eq!(span.kind(), SpanKind::Server);
+    assert_eq!(hex::encode(&span.trace_id), TRACE_ID);
+    assert_eq!(hex::encode(&span.parent_span_id), PARENT_ID);
+}
+
+#[tokio::test]
+async fn call_without_trace_context_starts_a_trace() {
+    let (collector, mut exports) = collector().await;
+    let backend = Backend::started(|command| {
+        command
+            .arg("--otlp-endpoint")
+            .arg(collector)
+            .env("OTEL_BSP_SCHEDULE_DELAY", "50");
+    })
+    .await;
+
+    backend.client().await.list_rooms(Empty {}).await.unwrap();
+
+    let (span, _) = exported(&mut exports, "/chat.ChatService/ListRooms").await;
+    assert_eq!(span.trace_id.len(), 16);
+    assert!(span.parent_span_id.is_empty());
+}
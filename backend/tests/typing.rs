@@ -0,0 +1,90 @@
+//! Typing notifications in a room.
+
+mod common;
+
+use std::time::Duration;
+
+use backend::proto::chat_event::Event;
+use backend::proto::{ChatEvent, ChatMessage, RecieveMsgRequest, SetTypingRequest, Typing};
+use tokio_stream::StreamExt;
+
+use common::{Backend, Client};
+
+async fn set_typing(client: &mut Client, typing: bool) {
+    let request = SetTypingRequest {
+        typing,
+        ..Default::default()
+    };
+    client.set_typing(request).await.unwrap();
+}
+
+/// Returns the next event of `events`, waiting at most `timeout`.
+async fn next(events: &mut tonic::Streaming<ChatEvent>, timeout: Duration) -> Option<Event> {
+    tokio::time::timeout(timeout, events.next())
+        .await
+        .expect("an event arrives")
+        .unwrap()
+        .unwrap()
+        .event
+}
+
+fn typing(user: &str, typing: bool) -> Option<Event> {
+    Some(Event::Typing(Typing {
+        room_id: String::from("general"),
+        user: user.to_string(),
+        typing,
+    }))
+}
+
+#[tokio::test]
+async fn announces_typing_once_until_the_message_is_sent() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+    let mut events = bob
+        .recieve_msg(RecieveMsgRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    // Renewals while still typing aren't announced again.
+    for _ in 0..3 {
+        set_typing(&mut alice, true).await;
+    }
+    let message = ChatMessage {
+        msg: String::from("hello"),
+        ..Default::default()
+    };
+    alice.send_msg(message).await.unwrap();
+
+    let wait = Duration::from_secs(5);
+    assert_eq!(next(&mut events, wait).await, typing("alice", true));
+    assert_eq!(next(&mut events, wait).await, typing("alice", false));
+    match next(&mut events, wait).await {
+        Some(Event::NewMessage(msg)) => assert_eq!(msg.msg, "hello"),
+        other => panic!("expected the message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn typing_runs_out_without_a_renewal() {
+    let backend = Backend::started(|_| {}).await;
+    let mut alice = backend.join("alice").await;
+    let mut bob = backend.join("bob").await;
+    let mut events = bob
+        .recieve_msg(RecieveMsgRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    set_typing(&mut alice, true).await;
+    assert_eq!(
+        next(&mut events, Duration::from_secs(5)).await,
+        typing("alice", true)
+    );
+    // The backend gives up after five seconds.
+    assert_eq!(
+        next(&mut events, Duration::from_secs(10)).await,
+        typing("alice", false)
+    );
+}
@@ -0,0 +1,60 @@
+//! Helpers for loading settings, shared by the backend and the frontend's
+//! server.
+
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+use crate::ratelimit::{InvalidQuota, Quota};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("{0} must be set together with {1}")]
+    Incomplete(&'static str, &'static str),
+    #[error("{0} must be positive")]
+    NotPositive(&'static str),
+}
+
+/// Reads a PEM certificate or key.
+pub fn read_pem(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    std::fs::read(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Reads a TOML config file.
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    toml::from_str(&text).map_err(|source| ConfigError::Parse {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// A quota from a rate and a burst, each given as its setting's name, value
+/// and default.
+pub fn quota(
+    (rate_name, rate, rate_default): (&'static str, Option<f64>, f64),
+    (burst_name, burst, burst_default): (&'static str, Option<u32>, u32),
+) -> Result<Quota, ConfigError> {
+    Quota::new(rate.unwrap_or(rate_default), burst.unwrap_or(burst_default)).map_err(|invalid| {
+        ConfigError::NotPositive(match invalid {
+            InvalidQuota::Rate => rate_name,
+            InvalidQuota::Burst => burst_name,
+        })
+    })
+}
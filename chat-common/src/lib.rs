@@ -0,0 +1,8 @@
+//! Server plumbing shared by the backend and the frontend's server.
+
+pub mod config;
+pub mod ratelimit;
+pub mod shutdown;
+pub mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -0,0 +1,113 @@
+//! Token bucket rate limiting.
+//!
+//! Every key, e.g. a user or a client address, gets a bucket of up to
+//! `burst` tokens that refills at `rate` tokens a second. Each limited call
+//! takes a token, and calls that find the bucket empty are refused with how
+//! long until the next one.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before the full ones, which act the same as new ones, are
+/// dropped.
+const PRUNE_ABOVE: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    /// Tokens added per second.
+    pub rate: f64,
+    /// Most tokens a bucket holds, so calls allowed at once.
+    pub burst: u32,
+}
+
+impl Quota {
+    /// A quota of `burst` calls at once, refilled at `rate` a second.
+    pub fn new(rate: f64, burst: u32) -> Result<Self, InvalidQuota> {
+        if rate.is_nan() || rate <= 0.0 {
+            return Err(InvalidQuota::Rate);
+        }
+        if burst == 0 {
+            return Err(InvalidQuota::Burst);
+        }
+        Ok(Self { rate, burst })
+    }
+}
+
+/// The part of a [`Quota`] that isn't positive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidQuota {
+    #[error("rate must be positive")]
+    Rate,
+    #[error("burst must be positive")]
+    Burst,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter<K> {
+    quota: Quota,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket, or returns how long until there is
+    /// one.
+    pub fn check(&self, key: K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// [`RateLimiter::check`] as of `now`.
+    pub fn check_at(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let Quota { rate, burst } = self.quota;
+        let burst = f64::from(burst);
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst)
+        };
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buckets.len() > PRUNE_ABOVE {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = bucket.updated.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Whole seconds to tell a refused client to wait, rounded up so it doesn't
+/// come back too early.
+pub fn retry_after(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// The message for a refused call, e.g. "Sending too fast, try again in 2
+/// seconds.", starting with `what`.
+pub fn refusal_message(what: &str, seconds: u64) -> String {
+    let unit = if seconds == 1 { "second" } else { "seconds" };
+    format!("{}, try again in {} {}.", what, seconds, unit)
+}
@@ -0,0 +1,111 @@
+//! Graceful shutdown.
+//!
+//! Event streams never end on their own, so a server would wait on them
+//! forever once it stops accepting new calls. Wrapping them in [`Draining`]
+//! ends them, after an optional last item, as soon as [`Shutdown::begin`] is
+//! called.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Ends every stream wrapped by this, now and from then on.
+    pub fn begin(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Waits until [`Shutdown::begin`] is called.
+    pub async fn started(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives in `self`, so this only returns once started.
+        let _ = receiver.wait_for(|closing| *closing).await;
+    }
+
+    /// Wraps `inner` to yield `farewell`, if any, and end on shutdown.
+    pub fn wrap<S: Stream>(&self, inner: S, farewell: Option<S::Item>) -> Draining<S> {
+        Draining {
+            inner,
+            closing: WatchStream::new(self.sender.subscribe()),
+            farewell,
+            done: false,
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stream that ends on shutdown, see [`Shutdown::wrap`].
+pub struct Draining<S: Stream> {
+    inner: S,
+    closing: WatchStream<bool>,
+    farewell: Option<S::Item>,
+    done: bool,
+}
+
+impl<S> Stream for Draining<S>
+where
+    S: Stream + Unpin,
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        while let Poll::Ready(Some(closing)) = Pin::new(&mut self.closing).poll_next(cx) {
+            if closing {
+                self.done = true;
+                return Poll::Ready(self.farewell.take());
+            }
+        }
+        let item = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(None) = item {
+            self.done = true;
+        }
+        item
+    }
+}
+
+/// Waits for SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => {}
+        () = terminate => {}
+    }
+}
@@ -0,0 +1,12 @@
+//! Helpers for the servers' integration tests, behind the `testing`
+//! feature.
+
+use std::net::{SocketAddr, TcpListener};
+
+/// Binds to find a free port, then releases it for the caller to use.
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
@@ -0,0 +1,78 @@
+//! Token buckets filling and emptying over time.
+
+use std::time::{Duration, Instant};
+
+use chat_common::ratelimit::{refusal_message, retry_after, InvalidQuota, Quota, RateLimiter};
+
+#[test]
+fn buckets_refill_over_time() {
+    let limiter = RateLimiter::new(Quota {
+        rate: 2.0,
+        burst: 3,
+    });
+    let start = Instant::now();
+
+    for _ in 0..3 {
+        limiter.check_at("alice", start).unwrap();
+    }
+    let wait = limiter.check_at("alice", start).unwrap_err();
+    assert_eq!(wait, Duration::from_millis(500));
+    // Every key has its own bucket.
+    limiter.check_at("bob", start).unwrap();
+
+    let later = start + Duration::from_millis(500);
+    limiter.check_at("alice", later).unwrap();
+    assert!(limiter.check_at("alice", later).is_err());
+    // Refills no further than the burst.
+    let much_later = start + Duration::from_secs(60);
+    for _ in 0..3 {
+        limiter.check_at("alice", much_later).unwrap();
+    }
+    assert!(limiter.check_at("alice", much_later).is_err());
+}
+
+#[test]
+fn time_going_backwards_refills_nothing() {
+    let limiter = RateLimiter::new(Quota {
+        rate: 1.0,
+        burst: 1,
+    });
+    let start = Instant::now() + Duration::from_secs(10);
+
+    limiter.check_at("alice", start).unwrap();
+    // A check racing an earlier one may read the clock first.
+    let earlier = start - Duration::from_secs(5);
+    assert!(limiter.check_at("alice", earlier).is_err());
+    assert!(limiter.check_at("alice", start).is_err());
+    limiter
+        .check_at("alice", start + Duration::from_secs(1))
+        .unwrap();
+}
+
+#[test]
+fn quotas_must_be_positive() {
+    assert!(Quota::new(0.5, 1).is_ok());
+    for rate in [0.0, -1.0, f64::NAN] {
+        assert_eq!(Quota::new(rate, 5).unwrap_err(), InvalidQuota::Rate);
+    }
+    assert_eq!(Quota::new(1.0, 0).unwrap_err(), InvalidQuota::Burst);
+}
+
+#[test]
+fn retry_after_rounds_up_to_whole_seconds() {
+    assert_eq!(retry_after(Duration::from_millis(1)), 1);
+    assert_eq!(retry_after(Duration::from_millis(1500)), 2);
+    assert_eq!(retry_after(Duration::from_secs(3)), 3);
+}
+
+#[test]
+fn refusal_messages_count_seconds() {
+    assert_eq!(
+        refusal_message("Sending too fast", 1),
+        "Sending too fast, try again in 1 second."
+    );
+    assert_eq!(
+        refusal_message("Sending too fast", 3),
+        "Sending too fast, try again in 3 seconds."
+    );
+}
@@ -0,0 +1,29 @@
+//! Streams wrapped to end on shutdown.
+
+use chat_common::shutdown::Shutdown;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn ends_streams_after_their_farewell() {
+    let shutdown = Shutdown::new();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let inner = tokio_stream::wrappers::UnboundedReceiverStream::new(receiver);
+    let mut stream = shutdown.wrap(inner, Some("bye"));
+
+    sender.send("hello").unwrap();
+    assert_eq!(stream.next().await, Some("hello"));
+    shutdown.begin();
+    sender.send("too late").unwrap();
+    assert_eq!(stream.next().await, Some("bye"));
+    assert_eq!(stream.next().await, None);
+}
+
+#[tokio::test]
+async fn streams_opened_after_shutdown_end_at_once() {
+    let shutdown = Shutdown::new();
+    shutdown.begin();
+    let mut stream = shutdown.wrap(tokio_stream::pending::<&str>(), None);
+
+    assert_eq!(stream.next().await, None);
+    shutdown.started().await;
+}
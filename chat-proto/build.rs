@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let grpc = std::env::var_os("CARGO_FEATURE_GRPC").is_some();
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_prost_build::configure()
+        .build_client(grpc)
+        .build_server(grpc)
+        .file_descriptor_set_path(out_dir.join("chat_descriptor.bin"))
+        .compile_protos(&["proto/chat.proto"], &["proto"])?;
+    Ok(())
+}
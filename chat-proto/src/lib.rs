@@ -0,0 +1,33 @@
+//! Protobuf types for the chat service, shared by the backend, the Leptos
+//! server and the WASM client so they can never drift apart.
+//!
+//! The tonic client and server stubs are only generated with the `grpc`
+//! feature.
+
+include!(concat!(env!("OUT_DIR"), "/chat.rs"));
+
+/// The encoded descriptors of `chat.proto`, for gRPC server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/chat_descriptor.bin"));
+
+/// Prefix of the room ids used for direct message conversations. Regular room
+/// ids are made of letters, digits and dashes, so they never start with it.
+const DIRECT_PREFIX: &str = "dm:";
+
+/// Returns the room id of the direct conversation between the users with ids
+/// `a` and `b`. The order of the users doesn't matter. Ids rather than names,
+/// so whoever takes a name next doesn't inherit its conversations.
+pub fn direct_room_id(a: &str, b: &str) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    // Length-prefixed so any pair of ids maps to a distinct room id.
+    format!("{}{}:{}{}", DIRECT_PREFIX, first.len(), first, second)
+}
+
+/// Returns the ids of the two users of a direct conversation, or `None` if
+/// `room_id` names a regular room.
+pub fn direct_participants(room_id: &str) -> Option<(&str, &str)> {
+    let rest = room_id.strip_prefix(DIRECT_PREFIX)?;
+    let (len, ids) = rest.split_once(':')?;
+    let len = len.parse().ok()?;
+    Some((ids.get(..len)?, ids.get(len..)?))
+}
@@ -1,56 +1,91 @@
 use crate::error_template::{AppError, ErrorTemplate};
+use crate::feed::{Feed, Row};
+use crate::framing;
 use futures::StreamExt;
 use leptos::prelude::*;
 use leptos::server_fn::codec::{ByteStream, Streaming};
 use leptos::task::spawn_local;
 use leptos_meta::*;
 use leptos_router::components::*;
-use leptos_router::StaticSegment;
+use leptos_router::hooks::{use_location, use_params_map, use_query_map};
+use leptos_router::location::Url;
+use leptos_router::{ParamSegment, StaticSegment};
+use chat_proto::chat_event::Event;
+use chat_proto::presence_event::Kind as PresenceKind;
+use chat_proto::{direct_participants, ChatEvent, ChatMessage, PresenceEvent, PresenceStatus, Room, SearchHit, SearchResponse};
 use prost::Message;
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
 
-// gRPC backend endpoint - can be overridden with GRPC_ENDPOINT environment variable at build time
-const GRPC_ENDPOINT: &str = match option_env!("GRPC_ENDPOINT") {
-    Some(endpoint) => endpoint,
-    None => "http://[::1]:50051",
-};
+/// Room shown on the home page; the backend creates it on first start.
+const DEFAULT_ROOM_ID: &str = "general";
 
-#[component]
-pub fn LoginWindow(is_logged_in: WriteSignal<bool>, username_handle: WriteSignal<String>) -> impl IntoView {
-    let (username, set_username) = signal(String::new());
+/// Login state shared by every route, so moving between rooms keeps the user
+/// signed in.
+#[derive(Clone, Copy)]
+struct Session {
+    username: RwSignal<String>,
+    logged_in: RwSignal<bool>,
+}
 
-    #[server]
-    pub async fn join(username: String) -> Result<bool, ServerFnError> {
-        // Validate username
-        let username = username.trim();
-        if username.is_empty() {
-            return Err(ServerFnError::new("Username cannot be empty".to_string()));
-        }
-        if username.len() > 50 {
-            return Err(ServerFnError::new("Username too long (max 50 characters)".to_string()));
-        }
-        if username.len() < 2 {
-            return Err(ServerFnError::new("Username too short (min 2 characters)".to_string()));
-        }
-        // Check for valid characters (alphanumeric, spaces, underscores, hyphens)
-        if !username.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-') {
-            return Err(ServerFnError::new("Username contains invalid characters".to_string()));
-        }
+/// Logs in as `username`, storing the session token in a cookie. Returns
+/// `false` when the name is taken.
+#[server]
+pub async fn join(username: String) -> Result<bool, ServerFnError> {
+    let reject = |reason: &str, message: &str| {
+        crate::metrics::join_failed(reason);
+        Err(ServerFnError::new(message.to_string()))
+    };
 
-        use backend::proto::chat_service_client::*;
-        let mut client = ChatServiceClient::connect(GRPC_ENDPOINT)
-            .await
-            .map_err(|e| ServerFnError::new(format!("Failed to establish connection with backend: {}", e)))?;
+    // Validate username
+    let username = username.trim();
+    if username.is_empty() {
+        return reject("empty", "Username cannot be empty");
+    }
+    if username.len() > 50 {
+        return reject("too_long", "Username too long (max 50 characters)");
+    }
+    if username.len() < 2 {
+        return reject("too_short", "Username too short (min 2 characters)");
+    }
+    // Check for valid characters (alphanumeric, spaces, underscores, hyphens)
+    if !username.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-') {
+        return reject("invalid_characters", "Username contains invalid characters");
+    }
 
-        let request = tonic::Request::new(backend::proto::User{ id: "0".into(), name: username.to_string() });
+    let mut client = crate::backend::client();
 
-        let response = client.join(request)
-            .await
-            .map_err(|e| ServerFnError::new(format!("Failed to query login: {}", e)))?
-            .into_inner();
+    // Forward any existing session so reloading the page can log back in
+    // under the same name.
+    let request = crate::session::request(chat_proto::User{ name: username.to_string(), ..Default::default() }).await;
+
+    let response = match client.join(request).await {
+        Ok(response) => response.into_inner(),
+        Err(e) => return reject("backend_error", &format!("Failed to query login: {}", e)),
+    };
 
-        Ok(response.error == 0)
+    if response.error == 0 {
+        crate::session::store_token(&response.token);
+    } else {
+        crate::metrics::join_failed("name_taken");
     }
+    Ok(response.error == 0)
+}
+
+/// The message of a failed server function call, without the error kind.
+/// Refusals for going too fast say when to try again.
+fn error_text(error: &ServerFnError) -> String {
+    match error {
+        ServerFnError::ServerError(message) => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[component]
+pub fn LoginWindow(is_logged_in: WriteSignal<bool>, username_handle: WriteSignal<String>) -> impl IntoView {
+    let (username, set_username) = signal(String::new());
+    let (error, set_error) = signal(None::<String>);
 
     view! {
         <div class="card card-compact w-96 h-96 bg-base-100 shadow-xl">
@@ -64,6 +99,7 @@ pub fn LoginWindow(is_logged_in: WriteSignal<bool>, username_handle: WriteSignal
                         } prop:value=username placeholder="Username" />
                     </label>
                 </div>
+                {move || error.get().map(|error| view! { <p class="text-sm text-error">{error}</p> })}
                 <div class="card-actions justify-end">
                     <button type="button" class="btn btn-primary" on:click=move |_| {
                         leptos::logging::log!("Login button clicked");
@@ -73,11 +109,18 @@ pub fn LoginWindow(is_logged_in: WriteSignal<bool>, username_handle: WriteSignal
                             match join(username_value.clone()).await {
                                 Ok(true) => {
                                     leptos::logging::log!("Join successful");
+                                    set_error.set(None);
                                     username_handle.set(username_value);
                                     is_logged_in.set(true);
                                 }
-                                Ok(false) => leptos::logging::log!("Join failed: returned false"),
-                                Err(e) => leptos::logging::log!("Join error: {:?}", e),
+                                Ok(false) => {
+                                    leptos::logging::log!("Join failed: returned false");
+                                    set_error.set(Some(String::from("That name is taken.")));
+                                }
+                                Err(e) => {
+                                    leptos::logging::log!("Join error: {:?}", e);
+                                    set_error.set(Some(error_text(&e)));
+                                }
                             }
                         });
                     }>
@@ -89,17 +132,83 @@ pub fn LoginWindow(is_logged_in: WriteSignal<bool>, username_handle: WriteSignal
     }
 }
 
-//This is the best way I could find to transfer the chat message from the server over to the
-//client. Create a replicated struct of whats on server side using prost which has functinality to
-//convert to and from bytes using prost::Message trait.
-#[derive(prost::Message)]
-struct ChatMessage {
-    #[prost(string, tag = "1")]
-    from: prost::alloc::string::String,
-    #[prost(string, tag = "2")]
-    msg: prost::alloc::string::String,
-    #[prost(string, tag = "3")]
-    time: prost::alloc::string::String,
+/// Delay before reconnecting after a stream drops, doubled after every
+/// attempt up to `MAX_RECONNECT_DELAY`.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+async fn sleep(duration: Duration) {
+    let (done, wait) = futures::channel::oneshot::channel();
+    set_timeout(move || { let _ = done.send(()); }, duration);
+    let _ = wait.await;
+}
+
+/// Spaces out the attempts to reopen a stream that dropped.
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { delay: INITIAL_RECONNECT_DELAY }
+    }
+
+    /// Starts over once the stream is open again.
+    fn reset(&mut self) {
+        self.delay = INITIAL_RECONNECT_DELAY;
+    }
+
+    /// Waits before the next attempt, longer each time.
+    async fn wait(&mut self) {
+        sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Runs `task` until the current owner is cleaned up, e.g. when the component
+/// that started it is disposed. A stream it reads is dropped right then,
+/// rather than on its next event.
+fn spawn_owned(task: impl std::future::Future<Output = ()> + 'static) {
+    let (task, handle) = futures::future::abortable(task);
+    on_cleanup(move || handle.abort());
+    spawn_local(async move {
+        let _ = task.await;
+    });
+}
+
+/// Formats the server timestamp as `HH:MM` in the viewer's local timezone.
+/// Messages are only rendered in the browser, so `Local` is the browser's zone.
+fn format_time(sent_at: Option<&prost_types::Timestamp>) -> String {
+    format_timestamp(sent_at, "%H:%M")
+}
+
+/// Like [`format_time`] but with the date, for messages outside their
+/// conversation.
+fn format_date_time(sent_at: Option<&prost_types::Timestamp>) -> String {
+    format_timestamp(sent_at, "%Y-%m-%d %H:%M")
+}
+
+fn format_timestamp(sent_at: Option<&prost_types::Timestamp>, format: &str) -> String {
+    sent_at
+        .and_then(|ts| chrono::DateTime::from_timestamp(ts.seconds, ts.nanos.max(0) as u32))
+        .map(|time| time.with_timezone(&chrono::Local).format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// Emojis offered by the reaction picker.
+const REACTION_EMOJIS: &[&str] = &["👍", "❤️", "😂", "😮", "😢", "🎉"];
+
+fn toggle_reaction(message_id: u64, emoji: String, remove: bool) {
+    spawn_local(async move {
+        let result = if remove {
+            remove_reaction(message_id, emoji).await
+        } else {
+            add_reaction(message_id, emoji).await
+        };
+        if let Err(e) = result {
+            leptos::logging::error!("Failed to update reaction: {:?}", e);
+        }
+    });
 }
 
 fn sha256_username(username: &str) -> String {
@@ -107,117 +216,627 @@ fn sha256_username(username: &str) -> String {
 
     hasher.update(username.as_bytes());
 
-    hex::encode(hasher
-        .finalize()
-        .to_vec())
+    hex::encode(hasher.finalize())
 }
 
-#[component]
-pub fn ChatWindow(username: String) -> impl IntoView {
-    let (messages, set_messages) = signal(vec![]);
+#[cfg(feature = "ssr")]
+mod chat_recv {
+    use chat_proto::*;
+    use futures::Stream;
 
-    #[cfg(feature = "ssr")]
-    mod chat_recv {
-        use backend::proto::*;
-        use futures::Stream;
+    pub async fn recv_message(room_id: String, after_id: Option<u64>) -> Result<impl Stream<Item = Result<ChatEvent, tonic::Status>>, Box<dyn std::error::Error>> {
+        let mut client = crate::backend::client();
 
-        pub async fn recv_message() -> Result<impl Stream<Item = Result<ChatMessage, tonic::Status>>, Box<dyn std::error::Error>> {
-            use chat_service_client::ChatServiceClient;
-            let mut client = ChatServiceClient::connect(super::GRPC_ENDPOINT)
-                .await?;
+        let request = crate::session::request(RecieveMsgRequest { room_id, after_id }).await;
 
-            let request = tonic::Request::new(Empty {});
+        let stream = client
+            .recieve_msg(request)
+            .await?
+            .into_inner();
 
-            let stream = client
-                .recieve_msg(request)
-                .await?
-                .into_inner();
+        Ok(stream)
+    }
+}
+
+/// Streams `ChatEvent` frames, starting with any after `after_id` when it
+/// is set. A page that loaded an empty room resumes after 0, so it still gets
+/// anything posted before its stream opened.
+#[server(output = Streaming)]
+pub async fn handle_messages(room_id: String, after_id: Option<u64>) -> Result<ByteStream, ServerFnError> {
+    let stream = chat_recv::recv_message(room_id, after_id)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to initialize message stream: {}", e)))?;
 
-            Ok(stream)
+    let data = stream.filter_map(|message| async move {
+        match message {
+            Ok(msg) => Some(Ok(framing::encode(&msg))),
+            Err(e) => {
+                leptos::logging::error!("Stream error: {:?}", e);
+                None
+            }
         }
-    }
+    });
+    let farewell = ChatEvent {
+        seq: 0,
+        event: Some(Event::Shutdown(chat_proto::Shutdown { reason: String::from("Server shutting down.") })),
+    };
+    let data = crate::shutdown::current().wrap(Box::pin(data), Some(Ok(framing::encode(&farewell))));
+    Ok(ByteStream::new(data))
+}
 
-    #[server(output = Streaming)]
-    pub async fn handle_messages() -> Result<ByteStream, ServerFnError> {
-        let stream = chat_recv::recv_message()
-            .await
-            .map_err(|e| ServerFnError::new(format!("Failed to initialize message stream: {}", e)))?;
-
-        let data = stream.filter_map(|message| async move {
-            match message {
-                Ok(msg) => {
-                    let mut buf = vec![];
-                    match msg.encode(&mut buf) {
-                        Ok(_) => Some(Ok(buf)),
-                        Err(e) => {
-                            leptos::logging::error!("Failed to encode message: {:?}", e);
-                            None
-                        }
-                    }
-                }
-                Err(e) => {
-                    leptos::logging::error!("Stream error: {:?}", e);
-                    None
+/// Keeps `feed` in step with `room_id`: replaces it with the feed returned
+/// by `load`, then applies the live events `keep` accepts. Resumes after the
+/// stream drops, and reloads from scratch when resuming fails. Typing
+/// updates from other users go to `typing` instead. Returns once `feed` has
+/// been disposed, e.g. after switching to another room; start it with
+/// [`spawn_owned`] so its stream is closed then too.
+async fn sync_events<F>(
+    room_id: String,
+    username: String,
+    feed: RwSignal<Feed>,
+    typing: Option<RwSignal<BTreeSet<String>>>,
+    load: impl Fn() -> F,
+    keep: impl Fn(&ChatEvent) -> bool,
+) where
+    F: std::future::Future<Output = Result<Feed, ServerFnError>>,
+{
+    // Seq of the newest event applied, used to resume after the stream
+    // drops. If resuming fails the snapshot is loaded from scratch.
+    let mut last_id = 0;
+    let mut resync = true;
+    let mut backoff = Backoff::new();
+
+    while !feed.is_disposed() {
+        if resync {
+            match load().await {
+                Ok(snapshot) => {
+                    last_id = snapshot.last_seq();
+                    feed.try_set(snapshot);
+                    resync = false;
                 }
+                Err(e) => leptos::logging::error!("Failed to load message history: {:?}", e),
             }
-        });
-        Ok(ByteStream::new(data))
-    }
+        }
 
-    Effect::new(move |_| {
-        spawn_local(async move {
-            match handle_messages().await {
+        if !resync {
+            match handle_messages(room_id.clone(), Some(last_id)).await {
                 Ok(byte_stream) => {
-                    let mut stream = byte_stream.into_inner();
-                    while let Some(Ok(message)) = stream.next().await {
-                        set_messages.update(|messages| messages.push(message));
+                    backoff.reset();
+                    let mut stream = std::pin::pin!(framing::decode_stream::<ChatEvent, _, _, _>(byte_stream.into_inner()));
+                    while let Some(event) = stream.next().await {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(e) => {
+                                leptos::logging::error!("Failed to decode event: {:?}", e);
+                                continue;
+                            }
+                        };
+                        // Typing updates aren't part of the history.
+                        if let Some(Event::Typing(update)) = event.event {
+                            // The typing set outlives the window, don't let
+                            // a stream for the previous room write to it.
+                            if feed.is_disposed() {
+                                break;
+                            }
+                            if let Some(typing) = typing.filter(|_| update.user != username) {
+                                typing.update(|typing| {
+                                    if update.typing {
+                                        typing.insert(update.user);
+                                    } else {
+                                        typing.remove(&update.user);
+                                    }
+                                });
+                            }
+                            continue;
+                        }
+                        // Nor is the notice sent before a server shuts down.
+                        // The stream ends next and is resumed like any drop.
+                        if let Some(Event::Shutdown(notice)) = &event.event {
+                            leptos::logging::log!("Reconnecting: {}", notice.reason);
+                            continue;
+                        }
+                        last_id = event.seq;
+                        if keep(&event) && feed.try_update(|feed| feed.apply(event)).is_none() {
+                            break;
+                        }
+                    }
+                    // Stop updates may have been missed while disconnected.
+                    if let Some(typing) = typing {
+                        typing.try_set(BTreeSet::new());
                     }
                 }
                 Err(e) => {
                     leptos::logging::error!("Failed to initialize message stream: {:?}", e);
+                    resync = true;
                 }
             }
-        });
-    });
+        }
 
-    let chat_messages = move || {
-        messages
-            .get()
+        backoff.wait().await;
+    }
+}
+
+/// UI state shared by the message bubbles of one list.
+#[derive(Clone, Copy)]
+struct BubbleState {
+    /// Id of the message being edited and its new text.
+    editing: RwSignal<Option<u64>>,
+    draft: RwSignal<String>,
+    /// Id of the message whose reaction picker is open.
+    picking: RwSignal<Option<u64>>,
+    /// Id of the message whose thread is open in the side panel.
+    thread: RwSignal<Option<u64>>,
+}
+
+impl BubbleState {
+    fn new(thread: RwSignal<Option<u64>>) -> Self {
+        Self {
+            editing: RwSignal::new(None),
+            draft: RwSignal::new(String::new()),
+            picking: RwSignal::new(None),
+            thread,
+        }
+    }
+}
+
+/// Renders one message. `replies` is the number of replies to show a thread
+/// link for, `None` inside a thread.
+fn message_bubble(message: ChatMessage, username: &str, state: BubbleState, replies: Option<usize>) -> impl IntoView {
+    let BubbleState { editing, draft, picking, thread } = state;
+    let id = message.id;
+    let own = message.from == username && !message.deleted;
+    let edited = message.edited && !message.deleted;
+    let deleted = message.deleted;
+    let text = message.msg.clone();
+    let bubble = move || if deleted {
+        view! { <div class="chat chat-bubble italic opacity-50">"This message was deleted."</div> }.into_any()
+    } else if editing.get() == Some(id) {
+        view! {
+            <div class="chat chat-bubble flex gap-1">
+                <input type="text" class="input input-bordered input-sm text-base-content" on:input=move |ev| {
+                    draft.set(event_target_value(&ev));
+                } prop:value=draft/>
+                <button class="btn btn-sm" on:click=move |_| {
+                    let msg = draft.get_untracked();
+                    spawn_local(async move {
+                        if let Err(e) = edit_message(id, msg).await {
+                            leptos::logging::error!("Failed to edit message: {:?}", e);
+                        }
+                    });
+                    editing.set(None);
+                }>"Save"</button>
+                <button class="btn btn-sm btn-ghost" on:click=move |_| editing.set(None)>"Cancel"</button>
+            </div>
+        }.into_any()
+    } else {
+        view! { <div class="chat chat-bubble">{text.clone()}</div> }.into_any()
+    };
+    let reactions = (!message.deleted).then(|| {
+        let buttons = message
+            .reactions
             .iter()
-            .rev()
-            .filter_map(|message| {
-                match Message::decode(&message[..]) {
-                    Ok(msg) => Some(msg),
-                    Err(e) => {
-                        leptos::logging::error!("Failed to decode message: {:?}", e);
-                        None
-                    }
-                }
-            })
-            .map(|message: ChatMessage| {
+            .map(|reaction| {
+                let emoji = reaction.emoji.clone();
+                let mine = reaction.users.iter().any(|user| user == username);
                 view! {
-                    <div class={ if message.from == username { "chat chat-start" } else { "chat chat-end" }}>
-                        <div class="chat-image avatar">
-                            <div class="w-10 rounded-full">
-                                <img alt={format!("Gravatar Identicon for {}", message.from.clone())}
-                        src={
-                            format!("https://www.gravatar.com/avatar/{}?d=identicon&f=y", sha256_username(&message.from))}
-                        />
-                            </div>
-                        </div>
-                        <div class="chat-header flex gap-2">
-                            {message.from.clone()}
-                            <time class="text xs opacity-50">{message.time.clone()}</time>
-                        </div>
-                        <div class="chat chat-bubble">{message.msg.clone()}</div>
-                    </div>
+                    <button class={ if mine { "btn btn-xs btn-active" } else { "btn btn-xs btn-ghost" }}
+                        title=reaction.users.join(", ")
+                        on:click=move |_| toggle_reaction(id, emoji.clone(), mine)>
+                        {format!("{} {}", reaction.emoji, reaction.users.len())}
+                    </button>
                 }
             })
-            .collect_view()
+            .collect_view();
+        let picker = move || {
+            (picking.get() == Some(id)).then(|| {
+                REACTION_EMOJIS
+                    .iter()
+                    .map(|emoji| view! {
+                        <button class="btn btn-xs btn-ghost" on:click=move |_| {
+                            picking.set(None);
+                            toggle_reaction(id, emoji.to_string(), false);
+                        }>{*emoji}</button>
+                    })
+                    .collect_view()
+            })
+        };
+        view! {
+            <div class="chat-footer flex flex-wrap gap-1">
+                {buttons}
+                <button class="btn btn-xs btn-ghost" title="Add reaction" on:click=move |_| {
+                    picking.update(|picking| *picking = if *picking == Some(id) { None } else { Some(id) });
+                }>"+"</button>
+                {picker}
+            </div>
+        }
+    });
+    let thread_link = replies.filter(|_| !message.deleted).map(|replies| {
+        let label = match replies {
+            0 => String::from("Reply"),
+            1 => String::from("1 reply"),
+            n => format!("{} replies", n),
+        };
+        view! {
+            <button class="btn btn-xs btn-ghost" on:click=move |_| thread.set(Some(id))>{label}</button>
+        }
+    });
+    let actions = own.then(|| {
+        let text = message.msg.clone();
+        view! {
+            <button class="btn btn-xs btn-ghost" on:click=move |_| {
+                draft.set(text.clone());
+                editing.set(Some(id));
+            }>"Edit"</button>
+            <button class="btn btn-xs btn-ghost" on:click=move |_| {
+                spawn_local(async move {
+                    if let Err(e) = delete_message(id).await {
+                        leptos::logging::error!("Failed to delete message: {:?}", e);
+                    }
+                });
+            }>"Delete"</button>
+        }
+    });
+    view! {
+        <div id=format!("msg-{}", id) class={ if message.from == username { "chat chat-start" } else { "chat chat-end" }}>
+            <div class="chat-image avatar">
+                <div class="w-10 rounded-full">
+                    <img alt={format!("Gravatar Identicon for {}", message.from.clone())}
+            src={
+                format!("https://www.gravatar.com/avatar/{}?d=identicon&f=y", sha256_username(&message.from))}
+            />
+                </div>
+            </div>
+            <div class="chat-header flex gap-2">
+                {message.from.clone()}
+                <time class="text xs opacity-50">{format_time(message.sent_at.as_ref())}</time>
+                {edited.then(|| view! { <span class="text-xs opacity-50">"(edited)"</span> })}
+            </div>
+            {bubble}
+            {reactions}
+            <div class="chat-footer flex gap-1">
+                {thread_link}
+                {actions}
+            </div>
+        </div>
+    }
+}
+
+/// Top-level messages fetched per page of history.
+#[cfg(feature = "ssr")]
+const HISTORY_PAGE: u32 = 50;
+
+/// Most top-level messages `ChatWindow` keeps, and so renders. Paging one
+/// way drops as many from the other end, to be fetched again when scrolled
+/// back to.
+const MAX_LOADED_MESSAGES: usize = 300;
+
+/// Distance from the top of the message list, in pixels, at which older
+/// messages start loading.
+const LOAD_MARGIN: i32 = 200;
+
+/// Shows the top-level messages of `room_id`. Other users typing in the room
+/// are kept in `typing` for the caller to display, and `thread` is set to
+/// the message whose replies should be shown.
+#[component]
+pub fn ChatWindow(
+    username: String,
+    room_id: String,
+    typing: RwSignal<BTreeSet<String>>,
+    thread: RwSignal<Option<u64>>,
+) -> impl IntoView {
+    let feed = RwSignal::new(Feed::default());
+    // Whether the server has messages older, or newer, than the ones loaded.
+    let has_older = RwSignal::new(false);
+    let has_newer = RwSignal::new(false);
+    let loading = RwSignal::new(false);
+    let list = NodeRef::<leptos::html::Div>::new();
+
+    /// Adds the user to the room's members so they are allowed to post in it.
+    #[server]
+    pub async fn join_room(room_id: String) -> Result<(), ServerFnError> {
+        let mut client = crate::backend::client();
+
+        client
+            .join_room(crate::session::request(chat_proto::RoomMembership { room_id }).await)
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to join room: {}", e.message())))?;
+
+        Ok(())
+    }
+
+    /// Fetches a page of top-level messages older than `before_id`, newer
+    /// than `after_id`, or the most recent ones if both are 0, as an encoded
+    /// `History`.
+    #[server]
+    pub async fn get_history(room_id: String, before_id: u64, after_id: u64) -> Result<Vec<u8>, ServerFnError> {
+        let mut client = crate::backend::client();
+
+        let request = chat_proto::HistoryRequest { limit: HISTORY_PAGE, room_id, before_id, after_id };
+        let history = client
+            .get_history(crate::session::request(request).await)
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to fetch history: {}", e)))?
+            .into_inner();
+
+        Ok(history.encode_to_vec())
+    }
+
+    /// Fetches and decodes a page of history.
+    async fn history_page(room_id: String, before_id: u64, after_id: u64) -> Result<chat_proto::History, ServerFnError> {
+        let bytes = get_history(room_id, before_id, after_id).await?;
+        chat_proto::History::decode(&bytes[..])
+            .map_err(|e| ServerFnError::new(format!("Failed to decode history: {}", e)))
+    }
+
+    // Loads the page before the oldest loaded message, or after the newest
+    // one, then drops messages from the other end once the feed is full.
+    let load_page = {
+        let room_id = room_id.clone();
+        move |older: bool| {
+            let (more, other_end) = if older { (has_older, has_newer) } else { (has_newer, has_older) };
+            if loading.get_untracked() || !more.get_untracked() {
+                return;
+            }
+            let Some((oldest, newest)) = feed.with_untracked(|feed| feed.oldest_id().zip(feed.newest_id())) else {
+                return;
+            };
+            let (before_id, after_id) = if older { (oldest, 0) } else { (0, newest) };
+            loading.set(true);
+            let room_id = room_id.clone();
+            spawn_local(async move {
+                match history_page(room_id, before_id, after_id).await {
+                    Ok(page) => {
+                        more.try_set(page.has_more);
+                        let trimmed = feed.try_update(|feed| {
+                            feed.extend_counted(page.messages, page.reply_counts);
+                            let full = feed.top_level() > MAX_LOADED_MESSAGES;
+                            if full && older {
+                                feed.trim_newest(MAX_LOADED_MESSAGES);
+                            } else if full {
+                                feed.trim_oldest(MAX_LOADED_MESSAGES);
+                            }
+                            full
+                        });
+                        if trimmed == Some(true) {
+                            other_end.try_set(true);
+                        }
+                    }
+                    Err(e) => leptos::logging::error!("Failed to load messages: {:?}", e),
+                }
+                loading.try_set(false);
+            });
+        }
+    };
+
+    // The feed is bottom-anchored: scrollTop is 0 while the newest message is
+    // in view and grows negative when scrolling up.
+    let at_top = move |el: &leptos::web_sys::HtmlDivElement| {
+        el.client_height() - el.scroll_top() >= el.scroll_height() - LOAD_MARGIN
+    };
+    let at_bottom = move |el: &leptos::web_sys::HtmlDivElement| el.scroll_top() >= -1;
+
+    // Only the newest messages are kept while following the conversation,
+    // so the feed doesn't grow for as long as the window stays open.
+    let trim = move || {
+        if feed.with_untracked(|feed| feed.top_level() > MAX_LOADED_MESSAGES) {
+            feed.update(|feed| feed.trim_oldest(MAX_LOADED_MESSAGES));
+            has_older.set(true);
+        }
+    };
+
+    let on_scroll = {
+        let load_page = load_page.clone();
+        move |_| {
+            let Some(el) = list.get_untracked() else {
+                return;
+            };
+            if at_top(&el) {
+                load_page(true);
+            } else if at_bottom(&el) {
+                load_page(false);
+                trim();
+            }
+        }
+    };
+
+    // Keeps the feed bounded as live messages arrive, and fills it when the
+    // loaded messages don't reach either end of the list yet.
+    Effect::new({
+        let load_page = load_page.clone();
+        move |_| {
+            feed.track();
+            has_older.track();
+            has_newer.track();
+            let Some(el) = list.get() else {
+                return;
+            };
+            if at_bottom(&el) {
+                load_page(false);
+                trim();
+            }
+            if at_top(&el) {
+                load_page(true);
+            }
+        }
+    });
+
+    Effect::new({
+        let username = username.clone();
+        move |_| {
+            let room_id = room_id.clone();
+            let username = username.clone();
+            spawn_owned(async move {
+                // Direct conversations have no membership, only their two users
+                // can read them.
+                if direct_participants(&room_id).is_none() {
+                    if let Err(e) = join_room(room_id.clone()).await {
+                        leptos::logging::error!("Failed to join room: {:?}", e);
+                    }
+                }
+
+                let load = {
+                    let room_id = room_id.clone();
+                    move || {
+                        let room_id = room_id.clone();
+                        async move {
+                            let history = history_page(room_id, 0, 0).await?;
+                            has_older.try_set(history.has_more);
+                            has_newer.try_set(false);
+                            Ok(Feed::counted(history.messages, history.reply_counts, history.last_seq))
+                        }
+                    }
+                };
+                // While the newest messages are dropped, new ones are left
+                // to paging too, appending them would leave a gap.
+                let keep = move |event: &ChatEvent| match &event.event {
+                    Some(Event::NewMessage(msg)) if msg.parent_id == 0 => !has_newer.get_untracked(),
+                    _ => true,
+                };
+                sync_events(room_id, username, feed, Some(typing), load, keep).await;
+            });
+        }
+    });
+
+    // Scroll to the message named in the URL, e.g. when coming from a
+    // search result, loading pages until it shows up.
+    let location = use_location();
+    let scrolled_to = StoredValue::new(String::new());
+    Effect::new(move |_| {
+        let hash = location.hash.get();
+        feed.track();
+        if hash.is_empty() || scrolled_to.with_value(|scrolled| *scrolled == hash) {
+            return;
+        }
+        let anchor = hash.trim_start_matches('#');
+        if let Some(el) = document().get_element_by_id(anchor) {
+            el.scroll_into_view();
+            scrolled_to.set_value(hash);
+            return;
+        }
+        let Some(target) = anchor.strip_prefix("msg-").and_then(|id| id.parse::<u64>().ok()) else {
+            return;
+        };
+        let (oldest, newest) = feed.with_untracked(|feed| (feed.oldest_id(), feed.newest_id()));
+        if oldest.is_some_and(|oldest| target < oldest) {
+            load_page(true);
+        } else if newest.is_some_and(|newest| target > newest) {
+            load_page(false);
+        }
+    });
+
+    let state = BubbleState::new(thread);
+
+    // Replies are shown in the thread panel, the feed only counts them.
+    // Newest first, the list is laid out bottom to top.
+    let rows = move || feed.with(|feed| feed.rows(|msg| msg.parent_id == 0).into_iter().rev().collect::<Vec<_>>());
+    let row_view = move |row: Row| {
+        feed.with_untracked(|feed| feed.get(row.id).cloned())
+            .map(|message| message_bubble(message, &username, state, Some(row.replies)))
     };
 
     view! {
-        <div class="overflow-auto flex flex-col-reverse flex-[0_0_90vh] h-full">{chat_messages}</div>
+        <div node_ref=list on:scroll=on_scroll class="chat-feed overflow-auto flex flex-col-reverse flex-[0_0_90vh] h-full">
+            <For each=rows key=|row| *row children=row_view/>
+            {move || loading.get().then(|| view! { <span class="loading loading-dots loading-sm mx-auto"></span> })}
+        </div>
+    }
+}
+
+/// Side panel showing a message and its replies, kept live by its own
+/// stream. Replies go to the direct conversation with `recipient` if set,
+/// otherwise to `room_id`.
+#[component]
+fn ThreadPanel(
+    parent_id: u64,
+    username: String,
+    room_id: String,
+    recipient: Option<String>,
+    thread: RwSignal<Option<u64>>,
+) -> impl IntoView {
+    let feed = RwSignal::new(Feed::default());
+    let (reply, set_reply) = signal(String::new());
+
+    /// Returns the thread as an encoded `Thread`.
+    #[server]
+    pub async fn get_thread(message_id: u64) -> Result<Vec<u8>, ServerFnError> {
+        let mut client = crate::backend::client();
+
+        let thread = client
+            .get_thread(crate::session::request(chat_proto::ThreadRequest { message_id }).await)
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to fetch thread: {}", e.message())))?
+            .into_inner();
+
+        Ok(thread.encode_to_vec())
+    }
+
+    /// Fetches and decodes the thread, the parent first.
+    async fn thread_messages(message_id: u64) -> Result<Vec<ChatMessage>, ServerFnError> {
+        let bytes = get_thread(message_id).await?;
+        let thread = chat_proto::Thread::decode(&bytes[..])
+            .map_err(|e| ServerFnError::new(format!("Failed to decode thread: {}", e)))?;
+        Ok(thread.parent.into_iter().chain(thread.replies).collect())
+    }
+
+    Effect::new({
+        let room_id = room_id.clone();
+        let username = username.clone();
+        move |_| {
+            let room_id = room_id.clone();
+            let username = username.clone();
+            spawn_owned(async move {
+                let in_thread = move |event: &ChatEvent| match &event.event {
+                    Some(Event::NewMessage(msg) | Event::Edited(msg) | Event::Deleted(msg) | Event::Reactions(msg)) => {
+                        msg.id == parent_id || msg.parent_id == parent_id
+                    }
+                    _ => false,
+                };
+                let load = move || async move { thread_messages(parent_id).await.map(Feed::new) };
+                sync_events(room_id, username, feed, None, load, in_thread).await;
+            });
+        }
+    });
+
+    let state = BubbleState::new(thread);
+    let rows = move || feed.with(|feed| feed.rows(|_| true));
+    let row_view = {
+        let username = username.clone();
+        move |row: Row| {
+            feed.with_untracked(|feed| feed.get(row.id).cloned())
+                .map(|message| message_bubble(message, &username, state, None))
+        }
+    };
+
+    view! {
+        <aside class="w-96 flex flex-col gap-2 p-2 bg-base-200">
+            <div class="flex items-center justify-between">
+                <h2 class="font-bold">"Thread"</h2>
+                <button class="btn btn-xs btn-ghost" on:click=move |_| thread.set(None)>"Close"</button>
+            </div>
+            <div class="overflow-auto flex flex-col flex-1">
+                <For each=rows key=|row| *row children=row_view/>
+            </div>
+            <div class="flex gap-1">
+                <input type="text" class="input input-bordered input-sm flex-1" on:input=move |ev| {
+                    set_reply.set(event_target_value(&ev));
+                } prop:value=reply placeholder="Reply"/>
+                <button class="btn btn-sm btn-primary" on:click=move |_| {
+                    let message = reply.get();
+                    let room_id = room_id.clone();
+                    let recipient = recipient.clone();
+                    spawn_local(async move {
+                        let sent = match recipient {
+                            Some(to) => send_direct(to, message, parent_id).await,
+                            None => send_message(room_id, message, parent_id).await,
+                        };
+                        if let Err(e) = sent {
+                            leptos::logging::error!("Failed to send reply: {:?}", e);
+                        }
+                    });
+                    set_reply.set(String::new());
+                }>"Send"</button>
+            </div>
+        </aside>
     }
 }
 
@@ -225,6 +844,10 @@ pub fn ChatWindow(username: String) -> impl IntoView {
 pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
+    provide_context(Session {
+        username: RwSignal::new(String::new()),
+        logged_in: RwSignal::new(false),
+    });
 
     view! {
         // injects a stylesheet into the document <head>
@@ -248,49 +871,617 @@ pub fn App() -> impl IntoView {
                     .into_view()
                 }>
                     <Route path=StaticSegment("") view=HomePage/>
+                    <Route path=(StaticSegment("room"), ParamSegment("id")) view=HomePage/>
+                    <Route path=(StaticSegment("dm"), ParamSegment("user")) view=HomePage/>
+                    <Route path=StaticSegment("search") view=SearchPage/>
                 </Routes>
             </main>
         </Router>
     }
 }
 
-/// Renders the home page of your application.
+/// Each entry is an encoded `Room`.
+#[server]
+pub async fn list_rooms() -> Result<Vec<Vec<u8>>, ServerFnError> {
+    let mut client = crate::backend::client();
+
+    let rooms = client
+        .list_rooms(tonic::Request::new(chat_proto::Empty {}))
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to list rooms: {}", e.message())))?
+        .into_inner();
+
+    Ok(rooms.rooms.iter().map(|room| room.encode_to_vec()).collect())
+}
+
+/// Sidebar listing every room, with a form to create a new one.
+#[component]
+fn RoomList(current_room: Memo<String>) -> impl IntoView {
+    let (new_room, set_new_room) = signal(String::new());
+
+    /// Returns the id of the new room.
+    #[server]
+    pub async fn create_room(name: String) -> Result<String, ServerFnError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ServerFnError::new("Room name cannot be empty".to_string()));
+        }
+        if name.len() > 50 {
+            return Err(ServerFnError::new("Room name too long (max 50 characters)".to_string()));
+        }
+
+        let mut client = crate::backend::client();
+
+        let room = client
+            .create_room(crate::session::request(chat_proto::CreateRoomRequest { name: name.to_string() }).await)
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to create room: {}", e.message())))?
+            .into_inner();
+
+        Ok(room.id)
+    }
+
+    #[server]
+    pub async fn leave_room(room_id: String) -> Result<(), ServerFnError> {
+        let mut client = crate::backend::client();
+
+        client
+            .leave_room(crate::session::request(chat_proto::RoomMembership { room_id }).await)
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to leave room: {}", e.message())))?;
+
+        Ok(())
+    }
+
+    #[server]
+    pub async fn leave() -> Result<(), ServerFnError> {
+        let mut client = crate::backend::client();
+
+        let result = client
+            .leave(crate::session::request(chat_proto::Empty {}).await)
+            .await;
+        crate::session::clear_token();
+        result.map_err(|e| ServerFnError::new(format!("Failed to log out: {}", e.message())))?;
+
+        Ok(())
+    }
+
+    let session = expect_context::<Session>();
+    let navigate = leptos_router::hooks::use_navigate();
+    let create = ServerAction::<CreateRoom>::new();
+    let rooms = Resource::new(move || create.version().get(), |_| list_rooms());
+
+    Effect::new({
+        let navigate = navigate.clone();
+        move |_| {
+            if let Some(Ok(room_id)) = create.value().get() {
+                set_new_room.set(String::new());
+                navigate(&format!("/room/{}", room_id), Default::default());
+            }
+        }
+    });
+
+    let room_links = move || {
+        rooms.get().map(|rooms| {
+            rooms
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|room| Room::decode(&room[..]).ok())
+                .map(|room| {
+                    let href = format!("/room/{}", room.id);
+                    let class = move || if current_room.get() == room.id { "active" } else { "" };
+                    view! { <li><A href=href attr:class=class>{room.name.clone()}</A></li> }
+                })
+                .collect_view()
+        })
+    };
+
+    let (search, set_search) = signal(String::new());
+
+    view! {
+        <aside class="w-56 flex flex-col gap-2 p-2 bg-base-200">
+            <input type="search" class="input input-bordered input-sm" on:input=move |ev| {
+                set_search.set(event_target_value(&ev));
+            } on:keydown={
+                let navigate = navigate.clone();
+                move |ev| {
+                    if ev.key() == "Enter" {
+                        let query = search.get_untracked();
+                        navigate(&format!("/search?q={}", Url::escape(&query)), Default::default());
+                    }
+                }
+            } prop:value=search placeholder="Search messages"/>
+            <ul class="menu">
+                <li class="menu-title">"Rooms"</li>
+                <Transition fallback=|| ()>{room_links}</Transition>
+            </ul>
+            <input type="text" class="input input-bordered input-sm" on:input=move |ev| {
+                set_new_room.set(event_target_value(&ev));
+            } prop:value=new_room placeholder="New room"/>
+            <button class="btn btn-sm" on:click=move |_| {
+                create.dispatch(CreateRoom { name: new_room.get_untracked() });
+            }>"Create room"</button>
+            <Show when=move || {
+                let room_id = current_room.get();
+                room_id != DEFAULT_ROOM_ID && direct_participants(&room_id).is_none()
+            }>
+                <button class="btn btn-sm btn-ghost" on:click={
+                    let navigate = navigate.clone();
+                    move |_| {
+                        let room_id = current_room.get_untracked();
+                        let navigate = navigate.clone();
+                        spawn_local(async move {
+                            if let Err(e) = leave_room(room_id).await {
+                                leptos::logging::error!("Failed to leave room: {:?}", e);
+                            }
+                            navigate("/", Default::default());
+                        });
+                    }
+                }>"Leave room"</button>
+            </Show>
+            <button class="btn btn-sm btn-ghost mt-auto" on:click=move |_| {
+                spawn_local(async move {
+                    if let Err(e) = leave().await {
+                        leptos::logging::error!("Failed to log out: {:?}", e);
+                    }
+                    session.username.set(String::new());
+                    session.logged_in.set(false);
+                });
+            }>"Log out"</button>
+        </aside>
+    }
+}
+
+/// Sidebar showing who is logged in, kept live by the presence stream.
+/// Clicking someone else opens a direct conversation with them.
+#[component]
+fn MemberList() -> impl IntoView {
+    let session = expect_context::<Session>();
+    // Member name to whether they currently have a chat window open.
+    let (members, set_members) = signal(BTreeMap::<String, bool>::new());
+
+    /// Streams `PresenceEvent` frames, starting with everyone already logged
+    /// in.
+    #[server(output = Streaming)]
+    pub async fn watch_presence() -> Result<ByteStream, ServerFnError> {
+        let mut client = crate::backend::client();
+
+        let stream = client
+            .watch_presence(tonic::Request::new(chat_proto::Empty {}))
+            .await
+            .map_err(|e| ServerFnError::new(format!("Failed to watch presence: {}", e.message())))?
+            .into_inner();
+
+        let data = stream.filter_map(|event| async move {
+            match event {
+                Ok(event) => Some(Ok(framing::encode(&event))),
+                Err(e) => {
+                    leptos::logging::error!("Presence stream error: {:?}", e);
+                    None
+                }
+            }
+        });
+        // Ends the stream on shutdown, the page reconnects like after any drop.
+        let data = crate::shutdown::current().wrap(Box::pin(data), None);
+        Ok(ByteStream::new(data))
+    }
+
+    Effect::new(move |_| {
+        spawn_owned(async move {
+            let mut backoff = Backoff::new();
+            // Until the list is disposed, e.g. after logging out.
+            while !set_members.is_disposed() {
+                match watch_presence().await {
+                    Ok(byte_stream) => {
+                        backoff.reset();
+                        let mut stream = std::pin::pin!(framing::decode_stream::<PresenceEvent, _, _, _>(byte_stream.into_inner()));
+                        while let Some(event) = stream.next().await {
+                            let event = match event {
+                                Ok(event) => event,
+                                Err(e) => {
+                                    leptos::logging::error!("Failed to decode presence event: {:?}", e);
+                                    continue;
+                                }
+                            };
+                            let Some(user) = event.user.as_ref() else { continue };
+                            let updated = set_members.try_update(|members| match event.kind() {
+                                PresenceKind::Left => {
+                                    members.remove(&user.name);
+                                }
+                                PresenceKind::Unspecified => {}
+                                _ => {
+                                    members.insert(user.name.clone(), user.status() == PresenceStatus::Online);
+                                }
+                            });
+                            if updated.is_none() {
+                                return;
+                            }
+                        }
+                        // Departures may have been missed while disconnected.
+                        // The next stream starts with everyone still online.
+                        set_members.try_set(BTreeMap::new());
+                    }
+                    Err(e) => leptos::logging::error!("Failed to initialize presence stream: {:?}", e),
+                }
+                backoff.wait().await;
+            }
+        });
+    });
+
+    let member_rows = move || {
+        members
+            .get()
+            .into_iter()
+            .map(|(name, online)| {
+                let badge = view! {
+                    <span class={ if online { "badge badge-xs badge-success" } else { "badge badge-xs badge-ghost" }}></span>
+                };
+                if name == session.username.get() {
+                    view! {
+                        <li><span class="flex items-center gap-2">{badge}{name}</span></li>
+                    }.into_any()
+                } else {
+                    view! {
+                        <li><A href=format!("/dm/{}", Url::escape(&name)) attr:class="flex items-center gap-2">{badge}{name}</A></li>
+                    }.into_any()
+                }
+            })
+            .collect_view()
+    };
+
+    view! {
+        <aside class="w-48 p-2 bg-base-200">
+            <ul class="menu">
+                <li class="menu-title">"Members"</li>
+                {member_rows}
+            </ul>
+        </aside>
+    }
+}
+
+/// Returns one page of search results as an encoded `SearchResponse`. `after`
+/// and `before` are optional `YYYY-MM-DD` dates; both days are included.
+#[server]
+pub async fn search_messages(
+    query: String,
+    from: String,
+    room_id: String,
+    after: String,
+    before: String,
+    page_token: u64,
+) -> Result<Vec<u8>, ServerFnError> {
+    let day = |date: &str, offset: i64| -> Result<Option<prost_types::Timestamp>, ServerFnError> {
+        if date.is_empty() {
+            return Ok(None);
+        }
+        let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| ServerFnError::new(format!("Invalid date {}: {}", date, e)))?;
+        let start = date.and_time(chrono::NaiveTime::MIN).and_utc() + chrono::Duration::days(offset);
+        Ok(Some(prost_types::Timestamp { seconds: start.timestamp(), nanos: 0 }))
+    };
+
+    let mut client = crate::backend::client();
+
+    let request = crate::session::request(chat_proto::SearchRequest {
+        query,
+        from,
+        room_id,
+        after: day(&after, 0)?,
+        before: day(&before, 1)?,
+        limit: 0,
+        page_token,
+    }).await;
+
+    let response = client
+        .search_messages(request)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to search: {}", e.message())))?
+        .into_inner();
+
+    Ok(response.encode_to_vec())
+}
+
+/// Link to `msg` in its conversation. Replies link to their thread's parent,
+/// since only top-level messages are shown in the feed.
+fn message_link(msg: &ChatMessage, username: &str) -> String {
+    let anchor = match msg.parent_id {
+        0 => msg.id,
+        parent_id => parent_id,
+    };
+    match direct_participants(&msg.room_id) {
+        // The room id holds user ids, the message the names.
+        Some(_) => {
+            let other = if msg.from == username { &msg.to } else { &msg.from };
+            format!("/dm/{}#msg-{}", Url::escape(other), anchor)
+        }
+        None => format!("/room/{}#msg-{}", msg.room_id, anchor),
+    }
+}
+
+/// Splits the snippet of `hit` into plain and highlighted parts.
+fn highlighted_snippet(hit: &SearchHit) -> impl IntoView {
+    let snippet = &hit.snippet;
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    for highlight in &hit.highlights {
+        let (start, end) = (highlight.start as usize, highlight.end as usize);
+        let (Some(before), Some(matched)) = (snippet.get(pos..start), snippet.get(start..end)) else {
+            continue;
+        };
+        parts.push(view! { {before.to_string()} }.into_any());
+        parts.push(view! { <mark>{matched.to_string()}</mark> }.into_any());
+        pos = end;
+    }
+    parts.push(view! { {snippet.get(pos..).unwrap_or_default().to_string()} }.into_any());
+    parts
+}
+
+/// Search form and results, driven by the query string so searches can be
+/// linked to and revisited.
+#[component]
+fn SearchPage() -> impl IntoView {
+    let session = expect_context::<Session>();
+    let query = use_query_map();
+    let navigate = leptos_router::hooks::use_navigate();
+    let param = move |name: &str| query.with_untracked(|query| query.get(name).unwrap_or_default());
+
+    let text = RwSignal::new(param("q"));
+    let from = RwSignal::new(param("from"));
+    let room = RwSignal::new(param("room"));
+    let after = RwSignal::new(param("after"));
+    let before = RwSignal::new(param("before"));
+
+    let hits = RwSignal::new(Vec::<SearchHit>::new());
+    let next_page = RwSignal::new(0u64);
+    let error = RwSignal::new(None::<String>);
+    let rooms = Resource::new(|| (), |_| list_rooms());
+
+    let fetch = move |page_token: u64| {
+        let get = |name: &str| query.with_untracked(|query| query.get(name).unwrap_or_default());
+        let (q, from, room, after, before) = (get("q"), get("from"), get("room"), get("after"), get("before"));
+        spawn_local(async move {
+            if q.trim().is_empty() {
+                return;
+            }
+            match search_messages(q, from, room, after, before, page_token).await {
+                Ok(bytes) => match SearchResponse::decode(&bytes[..]) {
+                    Ok(response) => {
+                        hits.update(|hits| hits.extend(response.hits));
+                        next_page.set(response.next_page_token);
+                    }
+                    Err(e) => leptos::logging::error!("Failed to decode search results: {:?}", e),
+                },
+                Err(e) => error.set(Some(error_text(&e))),
+            }
+        });
+    };
+
+    // A new query string starts a new search.
+    Effect::new(move |_| {
+        query.track();
+        hits.set(Vec::new());
+        next_page.set(0);
+        error.set(None);
+        fetch(0);
+    });
+
+    let submit = move |_| {
+        let params = [("q", text), ("from", from), ("room", room), ("after", after), ("before", before)]
+            .into_iter()
+            .map(|(name, value)| (name, value.get_untracked()))
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| format!("{}={}", name, Url::escape(&value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        navigate(&format!("/search?{}", params), Default::default());
+    };
+
+    let room_options = move || {
+        rooms.get().map(|rooms| {
+            rooms
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|room| Room::decode(&room[..]).ok())
+                .map(|option| {
+                    let selected = option.id == room.get_untracked();
+                    view! { <option value=option.id.clone() selected=selected>{option.name.clone()}</option> }
+                })
+                .collect_view()
+        })
+    };
+
+    let results = move || {
+        let username = session.username.get();
+        hits.get()
+            .into_iter()
+            .filter_map(|hit| {
+                let msg = hit.message.clone()?;
+                Some(view! {
+                    <li class="p-2 border-b border-base-300">
+                        <A href=message_link(&msg, &username)>
+                            <div class="text-sm opacity-70 flex gap-2">
+                                <span>{msg.from.clone()}</span>
+                                <span>{direct_participants(&msg.room_id).map_or_else(|| msg.room_id.clone(), |_| String::from("direct message"))}</span>
+                                <time>{format_date_time(msg.sent_at.as_ref())}</time>
+                            </div>
+                            <div>{highlighted_snippet(&hit)}</div>
+                        </A>
+                    </li>
+                })
+            })
+            .collect_view()
+    };
+
+    view! {
+        <div class="flex flex-col gap-2 p-4 max-w-3xl w-full mx-auto text-left">
+            <div class="flex items-center justify-between">
+                <h1 class="text-xl font-bold">"Search"</h1>
+                <A href="/">"Back to chat"</A>
+            </div>
+            <div class="flex flex-wrap gap-2">
+                <input type="search" class="input input-bordered input-sm flex-1" on:input=move |ev| text.set(event_target_value(&ev))
+                    on:keydown={
+                        let submit = submit.clone();
+                        move |ev| if ev.key() == "Enter" { submit(()) }
+                    } prop:value=text placeholder="Words to find"/>
+                <input type="text" class="input input-bordered input-sm w-32" on:input=move |ev| from.set(event_target_value(&ev)) prop:value=from placeholder="Author"/>
+                <select class="select select-bordered select-sm" on:change=move |ev| room.set(event_target_value(&ev))>
+                    <option value="" selected=move || room.get().is_empty()>"All rooms"</option>
+                    <Transition fallback=|| ()>{room_options}</Transition>
+                </select>
+                <input type="date" class="input input-bordered input-sm" on:input=move |ev| after.set(event_target_value(&ev)) prop:value=after title="From"/>
+                <input type="date" class="input input-bordered input-sm" on:input=move |ev| before.set(event_target_value(&ev)) prop:value=before title="Until"/>
+                <button class="btn btn-sm btn-primary" on:click=move |_| submit(())>"Search"</button>
+            </div>
+            {move || error.get().map(|error| view! { <div class="alert alert-error">{error}</div> })}
+            <ul>{results}</ul>
+            <Show when=move || next_page.get() != 0>
+                <button class="btn btn-sm" on:click=move |_| fetch(next_page.get_untracked())>"Load more"</button>
+            </Show>
+        </div>
+    }
+}
+
+/// Minimum time between two `SetTyping` calls while the user keeps typing.
+/// Shorter than the backend's expiry, so the indicator doesn't flicker.
+const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+
+/// Describes who is typing, e.g. "alice and bob are typing…".
+fn typing_text(typing: &BTreeSet<String>) -> String {
+    let names: Vec<_> = typing.iter().map(String::as_str).collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [name] => format!("{} is typing…", name),
+        [first, second] => format!("{} and {} are typing…", first, second),
+        _ => String::from("Several people are typing…"),
+    }
+}
+
+/// Renders the home page of your application. Also serves `/room/:id`, which
+/// shows that room instead of the default one, and `/dm/:user`, which shows
+/// the direct conversation with that user.
 #[component]
 fn HomePage() -> impl IntoView {
+    let session = expect_context::<Session>();
+    let params = use_params_map();
+    let recipient = Memo::new(move |_| params.get().get("user"));
+    // Direct conversations are keyed by user ids, which only the backend
+    // knows, so it names the room.
+    let direct = Resource::new(
+        move || (session.username.get(), recipient.get()),
+        |(_, user)| async move {
+            match user {
+                Some(user) => direct_room(user).await.map(Some),
+                None => Ok(None),
+            }
+        },
+    );
+    let room_id = Memo::new(move |_| match recipient.get() {
+        // Empty until the backend has named the conversation.
+        Some(_) => direct.get().and_then(Result::ok).flatten().unwrap_or_default(),
+        None => params
+            .get()
+            .get("id")
+            .unwrap_or_else(|| DEFAULT_ROOM_ID.to_string()),
+    });
     // Creates a reactive value to update the button
-    let (username, set_username) = signal(String::new());
     let (message, set_message) = signal(String::new());
-    let (logged_in, set_logged_in) = signal(false);
+    // Why the last message couldn't be sent, e.g. to wait before retrying.
+    let send_error = RwSignal::new(None::<String>);
+    // Other users typing in the current room, filled in by `ChatWindow`.
+    let typing = RwSignal::new(BTreeSet::new());
+    // Set while a `SetTyping` was sent recently, so keystrokes don't each
+    // call the backend.
+    let typing_sent = StoredValue::new(false);
+    // Message whose thread is open in the side panel, closed on room change.
+    let thread = RwSignal::new(None::<u64>);
+    Effect::new(move |_| {
+        room_id.track();
+        thread.set(None);
+    });
+
+    let notify_typing = move |text: &str| {
+        let room_id = room_id.get_untracked();
+        if room_id.is_empty() {
+            return;
+        }
+        if text.is_empty() {
+            typing_sent.set_value(false);
+            spawn_local(async move {
+                let _ = set_typing(room_id, false).await;
+            });
+        } else if !typing_sent.get_value() {
+            typing_sent.set_value(true);
+            set_timeout(move || typing_sent.set_value(false), TYPING_THROTTLE);
+            spawn_local(async move {
+                if let Err(e) = set_typing(room_id, true).await {
+                    leptos::logging::error!("Failed to send typing state: {:?}", e);
+                }
+            });
+        }
+    };
 
     view! {
         <div>
-        { move || if !logged_in.get() {
+        { move || if !session.logged_in.get() {
                 view!{
                     <div class="flex place-items-center justify-center w-full min-h-screen">
-                        <LoginWindow is_logged_in=set_logged_in username_handle=set_username/>
+                        <LoginWindow is_logged_in=session.logged_in.write_only() username_handle=session.username.write_only()/>
                     </div>
                 }.into_any()
             } else {
                 view!{
-                    <>
-                        <ChatWindow username=username.get()/>
-                        <div class="flex flex-1 place-content-center gap-1">
-                            <input type="text" class="input input-bordered flex-[0_0_80vw]" on:input=move |ev| {
-                                set_message.set(event_target_value(&ev));
-                            } prop:value=message placeholder="Enter text here."/>
-                            <button class="btn btn-primary" on:click=move |_| {
-                                let message = message.get();
-                                let username = username.get();
-
-                                spawn_local(async move {
-                                    if let Err(e) = send_message(username, message).await {
-                                        leptos::logging::error!("Failed to send message: {:?}", e);
-                                    }
-                                });
-                                set_message.set("".into());
-                            }>"Send"</button>
+                    <div class="flex min-h-screen">
+                        <RoomList current_room=room_id/>
+                        <div class="flex flex-col flex-1">
+                            {move || recipient.get().map(|user| view! {
+                                <h2 class="p-2 font-bold">{format!("Direct messages with {}", user)}</h2>
+                                {move || direct.get().and_then(Result::err).map(|e| view! { <p class="px-2 text-sm text-error">{error_text(&e)}</p> })}
+                            })}
+                            // Re-created whenever the route switches rooms
+                            {move || {
+                                let room_id = room_id.get();
+                                (!room_id.is_empty()).then(|| view! { <ChatWindow username=session.username.get() room_id typing thread/> })
+                            }}
+                            <div class="h-6 px-2 text-sm italic opacity-70">{move || typing_text(&typing.get())}</div>
+                            <div class="flex flex-1 place-content-center gap-1">
+                                <input type="text" class="input input-bordered flex-[0_0_60vw]" on:input=move |ev| {
+                                    let text = event_target_value(&ev);
+                                    notify_typing(&text);
+                                    set_message.set(text);
+                                } prop:value=message placeholder="Enter text here."/>
+                                <button class="btn btn-primary" on:click=move |_| {
+                                    let message = message.get();
+                                    let room_id = room_id.get();
+                                    let recipient = recipient.get();
+
+                                    spawn_local(async move {
+                                        let sent = match recipient {
+                                            Some(to) => send_direct(to, message.clone(), 0).await,
+                                            None => send_message(room_id, message.clone(), 0).await,
+                                        };
+                                        match sent {
+                                            Ok(()) => send_error.set(None),
+                                            Err(e) => {
+                                                leptos::logging::error!("Failed to send message: {:?}", e);
+                                                send_error.set(Some(error_text(&e)));
+                                                // Keep the text to send again unless something else was typed.
+                                                set_message.update(|text| if text.is_empty() { *text = message; });
+                                            }
+                                        }
+                                    });
+                                    set_message.set("".into());
+                                    // The backend clears the typing state once the message is posted.
+                                    typing_sent.set_value(false);
+                                }>"Send"</button>
+                            </div>
+                            {move || send_error.get().map(|error| view! { <p class="px-2 text-sm text-error">{error}</p> })}
                         </div>
-                    </>
+                        {move || thread.get().map(|parent_id| view! {
+                            <ThreadPanel parent_id username=session.username.get() room_id=room_id.get() recipient=recipient.get() thread/>
+                        })}
+                        <MemberList/>
+                    </div>
                 }.into_any()
             }
         }
@@ -298,8 +1489,11 @@ fn HomePage() -> impl IntoView {
     }
 }
 
+/// Posts `msg` to `room_id` as the user of the current session; the backend
+/// fills in the author from the session cookie. A non-zero `parent_id` posts
+/// it as a reply in that message's thread.
 #[server]
-pub async fn send_message(from: String, msg: String) -> Result<(), ServerFnError> {
+pub async fn send_message(room_id: String, msg: String, parent_id: u64) -> Result<(), ServerFnError> {
     // Validate message
     let msg = msg.trim();
     if msg.is_empty() {
@@ -309,25 +1503,134 @@ pub async fn send_message(from: String, msg: String) -> Result<(), ServerFnError
         return Err(ServerFnError::new("Message too long (max 1000 characters)".to_string()));
     }
 
-    // Validate username
-    let from = from.trim();
-    if from.is_empty() {
-        return Err(ServerFnError::new("Username cannot be empty".to_string()));
+    let mut client = crate::backend::client();
+
+    let request = crate::session::request(chat_proto::ChatMessage {
+        msg: msg.to_string(),
+        room_id,
+        parent_id,
+        ..Default::default()
+    }).await;
+
+
+    client
+        .send_msg(request)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to send message: {}", e.message())))?;
+
+    Ok(())
+}
+
+/// Tells the other users in `room_id` whether the current user is typing.
+#[server]
+pub async fn set_typing(room_id: String, typing: bool) -> Result<(), ServerFnError> {
+    let mut client = crate::backend::client();
+
+    client
+        .set_typing(crate::session::request(chat_proto::SetTypingRequest { room_id, typing }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to set typing state: {}", e.message())))?;
+
+    Ok(())
+}
+
+/// Returns the id of the current user's direct conversation with `user`,
+/// who must be logged in.
+#[server]
+pub async fn direct_room(user: String) -> Result<String, ServerFnError> {
+    let mut client = crate::backend::client();
+
+    let room = client
+        .get_direct_room(crate::session::request(chat_proto::DirectRoomRequest { user }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to open direct messages: {}", e.message())))?
+        .into_inner();
+
+    Ok(room.id)
+}
+
+/// Sends `msg` privately to `to`. Only the two of them see it, in the
+/// conversation at `/dm/:user`.
+#[server]
+pub async fn send_direct(to: String, msg: String, parent_id: u64) -> Result<(), ServerFnError> {
+    let msg = msg.trim();
+    if msg.is_empty() {
+        return Err(ServerFnError::new("Message cannot be empty".to_string()));
+    }
+    if msg.len() > 1000 {
+        return Err(ServerFnError::new("Message too long (max 1000 characters)".to_string()));
     }
 
-    use chrono::{Local, Timelike};
-    use backend::proto::chat_service_client::ChatServiceClient;
-    let mut client = ChatServiceClient::connect(GRPC_ENDPOINT).await?;
-    let current_time = Local::now();
+    let mut client = crate::backend::client();
 
-    let request = tonic::Request::new(backend::proto::ChatMessage {
-        from: from.to_string(),
+    let request = crate::session::request(chat_proto::DirectMessage {
+        to,
         msg: msg.to_string(),
-        time: format!("{:02}:{:02}", current_time.hour(), current_time.minute()),
-    });
+        parent_id,
+    }).await;
+
+    client
+        .send_direct(request)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to send direct message: {}", e.message())))?;
+
+    Ok(())
+}
 
+/// Replaces the text of one of the current user's messages.
+#[server]
+pub async fn edit_message(id: u64, msg: String) -> Result<(), ServerFnError> {
+    let msg = msg.trim();
+    if msg.is_empty() {
+        return Err(ServerFnError::new("Message cannot be empty".to_string()));
+    }
+    if msg.len() > 1000 {
+        return Err(ServerFnError::new("Message too long (max 1000 characters)".to_string()));
+    }
+
+    let mut client = crate::backend::client();
+
+    client
+        .edit_message(crate::session::request(chat_proto::EditMessageRequest { id, msg: msg.to_string() }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to edit message: {}", e.message())))?;
+
+    Ok(())
+}
+
+/// Deletes a message; everyone in the room sees it replaced by a tombstone.
+#[server]
+pub async fn delete_message(id: u64) -> Result<(), ServerFnError> {
+    let mut client = crate::backend::client();
+
+    client
+        .delete_message(crate::session::request(chat_proto::DeleteMessageRequest { id }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to delete message: {}", e.message())))?;
+
+    Ok(())
+}
+
+#[server]
+pub async fn add_reaction(message_id: u64, emoji: String) -> Result<(), ServerFnError> {
+    let mut client = crate::backend::client();
+
+    client
+        .add_reaction(crate::session::request(chat_proto::ReactionRequest { message_id, emoji }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to add reaction: {}", e.message())))?;
+
+    Ok(())
+}
+
+#[server]
+pub async fn remove_reaction(message_id: u64, emoji: String) -> Result<(), ServerFnError> {
+    let mut client = crate::backend::client();
 
-    let _response = client.send_msg(request).await?;
+    client
+        .remove_reaction(crate::session::request(chat_proto::ReactionRequest { message_id, emoji }).await)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to remove reaction: {}", e.message())))?;
 
     Ok(())
 }
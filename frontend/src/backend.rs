@@ -0,0 +1,51 @@
+//! Connection from the server functions to the gRPC backend.
+//!
+//! All requests share one [`Channel`]. It connects on first use rather than
+//! at startup, so the frontend can come up before the backend, and reconnects
+//! on the next call after the backend restarts or the connection drops. Calls
+//! made while the backend is down fail with `Unavailable` once the connect
+//! timeout passes.
+
+use chat_proto::chat_service_client::ChatServiceClient;
+use leptos::prelude::*;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status};
+
+type Interceptor = fn(Request<()>) -> Result<Request<()>, Status>;
+
+/// A client whose calls carry the trace context of the span they're made in.
+pub type Client = ChatServiceClient<InterceptedService<Channel, Interceptor>>;
+
+fn propagate(mut request: Request<()>) -> Result<Request<()>, Status> {
+    crate::telemetry::inject(request.metadata_mut());
+    Ok(request)
+}
+
+/// The shared channel, provided as context to every request.
+#[derive(Debug, Clone)]
+pub struct Backend {
+    channel: Channel,
+}
+
+impl Backend {
+    /// Must be called within a Tokio runtime, which drives the connection.
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            channel: endpoint.connect_lazy(),
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    pub fn client(&self) -> Client {
+        ChatServiceClient::with_interceptor(self.channel(), propagate as Interceptor)
+    }
+}
+
+/// A client on the [`Backend`] of the current request.
+pub fn client() -> Client {
+    expect_context::<Backend>().client()
+}
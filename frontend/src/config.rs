@@ -0,0 +1,263 @@
+//! Settings for the frontend server.
+//!
+//! Each setting is taken from the first of these that has it: a command line
+//! flag, an environment variable, the TOML file named by `--config`, and
+//! finally its default. The site itself (address, output paths) is still
+//! configured by cargo-leptos through `LEPTOS_*` variables; `listen` only
+//! overrides its address.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chat_common::config::{quota, read_pem, read_toml, ConfigError as SettingsError};
+use clap::Parser;
+use serde::Deserialize;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+use crate::ratelimit::Quota;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+    #[error("invalid backend URL {0}: {1}")]
+    Backend(String, tonic::transport::Error),
+    #[error("tls_* settings need an https:// backend URL")]
+    TlsWithoutHttps,
+}
+
+/// One layer of settings. Unset fields fall through to the next layer.
+#[derive(Debug, Default, Parser, Deserialize)]
+#[command(version, about = "Leptos frontend for the chat backend")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// TOML file to read settings from, with the same names as the flags
+    /// written in snake_case.
+    #[arg(long, env = "CHAT_FRONTEND_CONFIG")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Address to serve the site on [default: the cargo-leptos site address].
+    #[arg(long, env = "CHAT_FRONTEND_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// URL of the gRPC backend [default: http://[::1]:50051].
+    #[arg(long, env = "GRPC_ENDPOINT")]
+    pub backend: Option<String>,
+
+    /// PEM CA certificates to verify the backend's certificate against,
+    /// instead of the system roots.
+    #[arg(long, env = "CHAT_FRONTEND_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate to present to a backend that requires one.
+    #[arg(long, env = "CHAT_FRONTEND_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `tls_cert`.
+    #[arg(long, env = "CHAT_FRONTEND_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Seconds to wait for a connection to the backend [default: 5].
+    #[arg(long, env = "CHAT_FRONTEND_CONNECT_TIMEOUT")]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds to wait for the backend to answer a call, or to start
+    /// answering a streaming one [default: 30].
+    #[arg(long, env = "CHAT_FRONTEND_REQUEST_TIMEOUT")]
+    pub request_timeout: Option<u64>,
+
+    /// Seconds between HTTP/2 pings to the backend, which find a connection
+    /// that silently died [default: 30].
+    #[arg(long, env = "CHAT_FRONTEND_KEEPALIVE_INTERVAL")]
+    pub keepalive_interval: Option<u64>,
+
+    /// Seconds to wait for a ping to be answered before dropping the
+    /// connection [default: 10].
+    #[arg(long, env = "CHAT_FRONTEND_KEEPALIVE_TIMEOUT")]
+    pub keepalive_timeout: Option<u64>,
+
+    /// Logins each client IP may attempt per second, on average
+    /// [default: 0.2].
+    #[arg(long, env = "CHAT_FRONTEND_JOIN_RATE")]
+    pub join_rate: Option<f64>,
+
+    /// Logins each client IP may attempt in a burst before `join_rate`
+    /// applies [default: 5].
+    #[arg(long, env = "CHAT_FRONTEND_JOIN_BURST")]
+    pub join_burst: Option<u32>,
+
+    /// Messages each client IP may send per second, on average
+    /// [default: 2].
+    #[arg(long, env = "CHAT_FRONTEND_SEND_RATE")]
+    pub send_rate: Option<f64>,
+
+    /// Messages each client IP may send in a burst before `send_rate`
+    /// applies [default: 10].
+    #[arg(long, env = "CHAT_FRONTEND_SEND_BURST")]
+    pub send_burst: Option<u32>,
+
+    /// Rate limit clients by the last address in `X-Forwarded-For` rather
+    /// than the connection's. Only set this behind a reverse proxy that
+    /// appends to the header, or clients can pick their own address
+    /// [default: false].
+    #[arg(long, env = "CHAT_FRONTEND_TRUST_FORWARDED_FOR")]
+    pub trust_forwarded_for: Option<bool>,
+
+    /// Seconds to wait on shutdown for requests in flight to finish before
+    /// closing their connections [default: 10].
+    #[arg(long, env = "CHAT_FRONTEND_DRAIN_TIMEOUT")]
+    pub drain_timeout: Option<u64>,
+
+    /// Log filter, e.g. `info` or `frontend=debug` [default: info].
+    #[arg(long, env = "CHAT_FRONTEND_LOG")]
+    pub log_level: Option<String>,
+
+    /// OpenTelemetry collector to export spans to over OTLP/gRPC, e.g.
+    /// `http://localhost:4317`. Spans aren't exported when unset.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// How to verify the backend and identify to it over TLS.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub ca: Option<PathBuf>,
+    /// Certificate and key files.
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+    /// Reads the certificates for `Endpoint::tls_config`.
+    pub fn client_config(&self) -> Result<ClientTlsConfig, ConfigError> {
+        let mut config = match &self.ca {
+            Some(ca) => ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(ca)?)),
+            None => ClientTlsConfig::new().with_native_roots(),
+        };
+        if let Some((cert, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        Ok(config)
+    }
+}
+
+/// Timeouts and keepalive for the connection to the backend.
+#[derive(Debug, Clone)]
+pub struct ChannelConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub keepalive_interval: Duration,
+    pub keepalive_timeout: Duration,
+}
+
+/// Requests each client IP may make to the limited server functions.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub join: Quota,
+    pub send: Quota,
+    /// Whether client IPs are taken from `X-Forwarded-For`.
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: Option<SocketAddr>,
+    pub backend: String,
+    pub tls: Option<TlsConfig>,
+    pub channel: ChannelConfig,
+    pub rate_limits: RateLimits,
+    pub drain_timeout: Duration,
+    pub log_level: String,
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Config {
+    /// Loads the settings from the command line, the environment and the
+    /// config file. Exits with a usage message on invalid flags.
+    pub fn load() -> Result<Self, ConfigError> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => read_toml(path)?,
+            None => Args::default(),
+        };
+        Self::from_layers(args, file)
+    }
+
+    /// The backend to connect to, over TLS for an `https://` URL.
+    pub fn endpoint(&self) -> Result<Endpoint, ConfigError> {
+        let invalid = |e| ConfigError::Backend(self.backend.clone(), e);
+        let endpoint = Endpoint::from_shared(self.backend.clone())
+            .map_err(invalid)?
+            .connect_timeout(self.channel.connect_timeout)
+            .timeout(self.channel.request_timeout)
+            .http2_keep_alive_interval(self.channel.keepalive_interval)
+            .keep_alive_timeout(self.channel.keepalive_timeout)
+            .keep_alive_while_idle(true);
+        let https = endpoint.uri().scheme_str() == Some("https");
+        let tls = match (&self.tls, https) {
+            (Some(tls), true) => tls.client_config()?,
+            (None, true) => ClientTlsConfig::new().with_native_roots(),
+            (Some(_), false) => return Err(ConfigError::TlsWithoutHttps),
+            (None, false) => return Ok(endpoint),
+        };
+        endpoint.tls_config(tls).map_err(invalid)
+    }
+
+    /// Combines two layers, `args` taking precedence over `file`.
+    pub fn from_layers(args: Args, file: Args) -> Result<Self, ConfigError> {
+        let identity = match (
+            args.tls_cert.or(file.tls_cert),
+            args.tls_key.or(file.tls_key),
+        ) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            (Some(_), None) => return Err(SettingsError::Incomplete("tls_cert", "tls_key").into()),
+            (None, Some(_)) => return Err(SettingsError::Incomplete("tls_key", "tls_cert").into()),
+        };
+        let ca = args.tls_ca.or(file.tls_ca);
+        let tls = (ca.is_some() || identity.is_some()).then_some(TlsConfig { ca, identity });
+        let rate_limits = RateLimits {
+            join: quota(
+                ("join_rate", args.join_rate.or(file.join_rate), 0.2),
+                ("join_burst", args.join_burst.or(file.join_burst), 5),
+            )?,
+            send: quota(
+                ("send_rate", args.send_rate.or(file.send_rate), 2.0),
+                ("send_burst", args.send_burst.or(file.send_burst), 10),
+            )?,
+            trust_forwarded_for: args
+                .trust_forwarded_for
+                .or(file.trust_forwarded_for)
+                .unwrap_or(false),
+        };
+        Ok(Self {
+            listen: args.listen.or(file.listen),
+            backend: args
+                .backend
+                .or(file.backend)
+                .unwrap_or_else(|| String::from("http://[::1]:50051")),
+            tls,
+            channel: ChannelConfig {
+                connect_timeout: seconds(args.connect_timeout.or(file.connect_timeout), 5),
+                request_timeout: seconds(args.request_timeout.or(file.request_timeout), 30),
+                keepalive_interval: seconds(
+                    args.keepalive_interval.or(file.keepalive_interval),
+                    30,
+                ),
+                keepalive_timeout: seconds(args.keepalive_timeout.or(file.keepalive_timeout), 10),
+            },
+            rate_limits,
+            drain_timeout: seconds(args.drain_timeout.or(file.drain_timeout), 10),
+            log_level: args
+                .log_level
+                .or(file.log_level)
+                .unwrap_or_else(|| String::from("info")),
+            otlp_endpoint: args.otlp_endpoint.or(file.otlp_endpoint),
+        })
+    }
+}
+
+fn seconds(value: Option<u64>, default: u64) -> Duration {
+    Duration::from_secs(value.unwrap_or(default))
+}
@@ -0,0 +1,256 @@
+//! Decoded messages of one conversation, as shown by the chat window and the
+//! thread panel.
+//!
+//! Events are applied to the message they concern, so a new event costs the
+//! same however long the conversation already is. [`Feed::rows`] gives each
+//! message a key that changes whenever the message does, for a keyed `<For>`
+//! that only renders the rows that changed.
+//!
+//! A thread keeps its replies. A room's feed, made with [`Feed::counted`],
+//! only keeps how many replies each message has, so long threads don't grow
+//! it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chat_proto::chat_event::Event;
+use chat_proto::{ChatEvent, ChatMessage};
+
+/// Loaded messages by id, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    messages: BTreeMap<u64, ChatMessage>,
+    /// Number of replies that aren't deleted, by the id of their parent.
+    replies: BTreeMap<u64, usize>,
+    /// Whether replies are kept, or only counted for their parent.
+    counts_only: bool,
+    /// Seq the reply counts were loaded as of.
+    counted_at: u64,
+    /// Number of loaded messages that aren't replies.
+    top_level: usize,
+}
+
+/// Identifies how a message is rendered: it changes on every edit, deletion
+/// or reaction, and when the message gets a reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Row {
+    pub id: u64,
+    pub revision: u64,
+    pub replies: usize,
+}
+
+impl Feed {
+    pub fn new(messages: impl IntoIterator<Item = ChatMessage>) -> Self {
+        let mut feed = Self::default();
+        feed.extend(messages);
+        feed
+    }
+
+    /// A feed of top-level messages that counts replies instead of keeping
+    /// them. `reply_counts` are the counts by parent id as of seq
+    /// `counted_at`; later replies are counted as they arrive.
+    pub fn counted(
+        messages: impl IntoIterator<Item = ChatMessage>,
+        reply_counts: HashMap<u64, u32>,
+        counted_at: u64,
+    ) -> Self {
+        let mut feed = Self {
+            counts_only: true,
+            counted_at,
+            ..Self::default()
+        };
+        feed.extend_counted(messages, reply_counts);
+        feed
+    }
+
+    /// Adds a page of top-level messages along with their reply counts, as
+    /// returned with them.
+    pub fn extend_counted(
+        &mut self,
+        messages: impl IntoIterator<Item = ChatMessage>,
+        reply_counts: HashMap<u64, u32>,
+    ) {
+        self.extend(messages.into_iter().filter(|msg| msg.parent_id == 0));
+        for (parent_id, count) in reply_counts {
+            if self.messages.contains_key(&parent_id) && count > 0 {
+                self.replies.insert(parent_id, count as usize);
+            }
+        }
+    }
+
+    /// Adds messages, e.g. an older page of history. Messages that are
+    /// already loaded are replaced.
+    pub fn extend(&mut self, messages: impl IntoIterator<Item = ChatMessage>) {
+        for msg in messages {
+            self.insert(msg);
+        }
+    }
+
+    /// Applies a live event. Changes to messages that aren't loaded, and
+    /// replies to them, are dropped.
+    pub fn apply(&mut self, event: ChatEvent) {
+        match event.event {
+            // Only new and deleted replies change the count.
+            Some(Event::NewMessage(msg) | Event::Deleted(msg))
+                if self.counts_only && msg.parent_id != 0 =>
+            {
+                if self.messages.contains_key(&msg.parent_id) {
+                    self.count(msg.parent_id, !msg.deleted);
+                }
+            }
+            // Replayed after a reconnect, keep the newer copy.
+            Some(Event::NewMessage(msg)) => {
+                if msg.parent_id == 0 || self.messages.contains_key(&msg.parent_id) {
+                    self.insert(msg);
+                }
+            }
+            Some(Event::Edited(msg) | Event::Deleted(msg) | Event::Reactions(msg)) => {
+                if self.messages.contains_key(&msg.id) {
+                    self.insert(msg);
+                }
+            }
+            Some(Event::Typing(_) | Event::Shutdown(_)) | None => {}
+        }
+    }
+
+    fn insert(&mut self, msg: ChatMessage) {
+        self.count_reply(&msg, true);
+        let top_level = msg.parent_id == 0;
+        match self.messages.insert(msg.id, msg) {
+            Some(old) => self.count_reply(&old, false),
+            None if top_level => self.top_level += 1,
+            None => {}
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(old) = self.messages.remove(&id) {
+            self.count_reply(&old, false);
+            if old.parent_id == 0 {
+                self.top_level -= 1;
+            }
+        }
+    }
+
+    /// Drops the oldest top-level messages, and the replies to them, until
+    /// at most `max` are left.
+    pub fn trim_oldest(&mut self, max: usize) {
+        let dropped: BTreeSet<u64> = self.top_level_ids().rev().skip(max).collect();
+        self.drop_threads(dropped);
+    }
+
+    /// Drops the newest top-level messages, and the replies to them, until
+    /// at most `max` are left.
+    pub fn trim_newest(&mut self, max: usize) {
+        let dropped: BTreeSet<u64> = self.top_level_ids().skip(max).collect();
+        self.drop_threads(dropped);
+    }
+
+    fn top_level_ids(&self) -> impl DoubleEndedIterator<Item = u64> + '_ {
+        self.messages
+            .values()
+            .filter(|msg| msg.parent_id == 0)
+            .map(|msg| msg.id)
+    }
+
+    /// Removes the messages in `parents` along with their replies.
+    fn drop_threads(&mut self, parents: BTreeSet<u64>) {
+        if parents.is_empty() {
+            return;
+        }
+        let dropped: Vec<u64> = self
+            .messages
+            .values()
+            .filter(|msg| parents.contains(&msg.id) || parents.contains(&msg.parent_id))
+            .map(|msg| msg.id)
+            .collect();
+        for id in dropped {
+            self.remove(id);
+        }
+        self.replies
+            .retain(|parent_id, _| !parents.contains(parent_id));
+    }
+
+    /// Keeps the reply count of `msg`'s parent in step as `msg` is added to
+    /// or removed from the feed.
+    fn count_reply(&mut self, msg: &ChatMessage, added: bool) {
+        if msg.parent_id != 0 && !msg.deleted {
+            self.count(msg.parent_id, added);
+        }
+    }
+
+    /// Counts one more or one less reply to `parent_id`.
+    fn count(&mut self, parent_id: u64, added: bool) {
+        let count = self.replies.entry(parent_id).or_default();
+        if added {
+            *count += 1;
+        } else {
+            *count = count.saturating_sub(1);
+        }
+        if *count == 0 {
+            self.replies.remove(&parent_id);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&ChatMessage> {
+        self.messages.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Number of loaded messages that aren't replies.
+    pub fn top_level(&self) -> usize {
+        self.top_level
+    }
+
+    /// Id of the oldest loaded top-level message, to fetch the page before
+    /// it.
+    pub fn oldest_id(&self) -> Option<u64> {
+        self.top_level_ids().next()
+    }
+
+    /// Id of the newest loaded top-level message, to fetch the page after
+    /// it.
+    pub fn newest_id(&self) -> Option<u64> {
+        self.top_level_ids().next_back()
+    }
+
+    /// Seq of the newest change to a loaded message or reply count, to
+    /// resume the live stream after it.
+    pub fn last_seq(&self) -> u64 {
+        self.messages
+            .values()
+            .map(|msg| msg.id.max(msg.revision))
+            .max()
+            .unwrap_or(0)
+            .max(self.counted_at)
+    }
+
+    /// Number of replies to `parent_id` that aren't deleted.
+    pub fn replies(&self, parent_id: u64) -> usize {
+        self.replies.get(&parent_id).copied().unwrap_or(0)
+    }
+
+    /// Every message, oldest first.
+    pub fn messages(&self) -> impl DoubleEndedIterator<Item = &ChatMessage> {
+        self.messages.values()
+    }
+
+    /// Rows for the messages `show` accepts, oldest first.
+    pub fn rows(&self, show: impl Fn(&ChatMessage) -> bool) -> Vec<Row> {
+        self.messages
+            .values()
+            .filter(|msg| show(msg))
+            .map(|msg| Row {
+                id: msg.id,
+                revision: msg.revision,
+                replies: self.replies(msg.id),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,121 @@
+//! Length-delimited framing for the protobuf messages streamed to the browser.
+//!
+//! A `ByteStream` is carried over a chunked HTTP response, and nothing keeps
+//! the chunks the browser sees lined up with the buffers the server wrote:
+//! they may be split or coalesced along the way. Each message is therefore
+//! sent prefixed with its length as a varint, the same framing as
+//! `Message::encode_length_delimited`, and reassembled on the client by a
+//! [`FrameDecoder`].
+
+use futures::{Stream, StreamExt};
+use prost::Message;
+use thiserror::Error;
+
+/// Largest frame accepted, the same as tonic's default message size limit.
+pub const MAX_FRAME_LEN: usize = 4 * 1024 * 1024;
+
+/// Longest varint encoding of a `u64`.
+const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    /// The length prefix is malformed or over [`MAX_FRAME_LEN`]. Framing is
+    /// lost and the rest of the stream can't be read.
+    #[error("invalid frame length")]
+    InvalidLength,
+    /// The frame arrived whole but isn't a valid message. Later frames are
+    /// unaffected.
+    #[error("failed to decode frame: {0}")]
+    Decode(#[from] prost::DecodeError),
+}
+
+/// Encodes `msg` as one frame.
+pub fn encode<M: Message>(msg: &M) -> Vec<u8> {
+    msg.encode_length_delimited_to_vec()
+}
+
+/// Reassembles frames from chunks however they were split or merged.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    /// Start of the first frame not yet returned.
+    pos: usize,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next chunk received.
+    pub fn push(&mut self, chunk: &[u8]) {
+        // Frames already returned are only dropped here, so returning several
+        // frames from one chunk doesn't move the rest each time.
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Returns the next message once all of its frame has arrived.
+    pub fn decode<M: Message + Default>(&mut self) -> Option<Result<M, FrameError>> {
+        let pending = &self.buf[self.pos..];
+        let (len, header) = match read_length(pending)? {
+            Ok(length) => length,
+            Err(e) => return Some(Err(e)),
+        };
+        let frame = pending.get(header..header + len)?;
+        let msg = M::decode(frame).map_err(FrameError::from);
+        self.pos += header + len;
+        Some(msg)
+    }
+
+    /// Number of bytes received that aren't part of a returned frame yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+}
+
+/// Reads the length prefix at the start of `buf`, returning the frame length
+/// and the size of the prefix, or `None` if the prefix isn't complete yet.
+fn read_length(buf: &[u8]) -> Option<Result<(usize, usize), FrameError>> {
+    let mut len: u64 = 0;
+    for (idx, byte) in buf.iter().take(MAX_VARINT_LEN).enumerate() {
+        len |= u64::from(byte & 0x7f) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Some(match usize::try_from(len) {
+                Ok(len) if len <= MAX_FRAME_LEN => Ok((len, idx + 1)),
+                _ => Err(FrameError::InvalidLength),
+            });
+        }
+    }
+    if buf.len() >= MAX_VARINT_LEN {
+        return Some(Err(FrameError::InvalidLength));
+    }
+    None
+}
+
+/// Decodes the frames carried by a stream of chunks. Ends when the chunks do,
+/// on the first transport error, or after an [`FrameError::InvalidLength`].
+pub fn decode_stream<M, S, B, E>(chunks: S) -> impl Stream<Item = Result<M, FrameError>>
+where
+    M: Message + Default,
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    let state = Some((Box::pin(chunks), FrameDecoder::new()));
+    futures::stream::unfold(state, |state| async move {
+        let (mut chunks, mut decoder) = state?;
+        loop {
+            match decoder.decode::<M>() {
+                Some(Err(FrameError::InvalidLength)) => {
+                    return Some((Err(FrameError::InvalidLength), None))
+                }
+                Some(frame) => return Some((frame, Some((chunks, decoder)))),
+                None => match chunks.next().await {
+                    Some(Ok(chunk)) => decoder.push(chunk.as_ref()),
+                    _ => return None,
+                },
+            }
+        }
+    })
+}
@@ -0,0 +1,47 @@
+//! Probes for orchestration.
+//!
+//! `/healthz` answers whenever the server is up. `/readyz` also asks the
+//! backend's `grpc.health.v1.Health` service whether the chat service is
+//! serving, so traffic only reaches a frontend whose backend can handle it.
+
+use std::time::Duration;
+
+use chat_proto::chat_service_server::SERVICE_NAME;
+use http::StatusCode;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+
+use crate::backend::Backend;
+
+/// Longest a readiness check waits for the backend, well below the usual
+/// probe timeouts.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks that the backend is reachable and its chat service is serving.
+/// Fails with the reason otherwise.
+pub async fn check(backend: &Backend) -> Result<(), String> {
+    let request = HealthCheckRequest {
+        service: SERVICE_NAME.to_string(),
+    };
+    let mut client = HealthClient::new(backend.channel());
+    let response = tokio::time::timeout(READY_TIMEOUT, client.check(request))
+        .await
+        .map_err(|_| String::from("backend health check timed out"))?
+        .map_err(|status| format!("backend unreachable: {}", status.message()))?;
+    match response.into_inner().status() {
+        ServingStatus::Serving => Ok(()),
+        status => Err(format!("backend is {}", status.as_str_name())),
+    }
+}
+
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+pub async fn readyz(backend: Backend) -> (StatusCode, String) {
+    match check(&backend).await {
+        Ok(()) => (StatusCode::OK, String::from("ready")),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    }
+}
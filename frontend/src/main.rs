@@ -5,7 +5,39 @@ async fn main() {
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use frontend::app::*;
+    use frontend::backend::Backend;
+    use frontend::config::Config;
     use frontend::fileserv::file_and_error_handler;
+    use frontend::health;
+    use frontend::metrics::{Metrics, MetricsLayer};
+    use frontend::ratelimit::RateLimitLayer;
+    use frontend::shutdown::{self, Shutdown};
+    use std::future::IntoFuture;
+    use frontend::telemetry;
+    use leptos::server_fn::ServerFn;
+    use tower_http::trace::TraceLayer;
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let _telemetry = match telemetry::init(&config.log_level, config.otlp_endpoint.as_deref()) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let backend = match config.endpoint() {
+        Ok(endpoint) => Backend::new(endpoint),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
     // Setting get_configuration(None) means we'll be using cargo-leptos's env values
     // For deployment these variables are:
@@ -14,12 +46,26 @@ async fn main() {
     // The file would need to be included with the executable when moved to deployment
     let conf = get_configuration(None).unwrap();
     let leptos_options = conf.leptos_options;
-    let addr = leptos_options.site_addr;
+    let addr = config.listen.unwrap_or(leptos_options.site_addr);
     let routes = generate_route_list(App);
+    let metrics = Metrics::new();
+    let shutdown = Shutdown::new();
+    let readyz = {
+        let backend = backend.clone();
+        move || health::readyz(backend.clone())
+    };
 
     // build our application with a route
     let app = Router::new()
-        .leptos_routes(&leptos_options, routes, {
+        .leptos_routes_with_context(&leptos_options, routes, {
+            let metrics = metrics.clone();
+            let shutdown = shutdown.clone();
+            move || {
+                provide_context(backend.clone());
+                provide_context(metrics.clone());
+                provide_context(shutdown.clone());
+            }
+        }, {
             let leptos_options = leptos_options.clone();
             move || {
                 use leptos::prelude::*;
@@ -41,14 +87,54 @@ async fn main() {
                 }
             }
         })
+        .route("/healthz", axum::routing::get(health::healthz))
+        .route("/readyz", axum::routing::get(readyz))
+        .route("/metrics", axum::routing::get({
+            let metrics = metrics.clone();
+            move || async move { metrics.encode() }
+        }))
         .fallback(file_and_error_handler)
+        .layer(
+            RateLimitLayer::new()
+                .limit(
+                    &[Join::PATH],
+                    config.rate_limits.join,
+                    "Too many login attempts",
+                )
+                .limit(
+                    &[SendMessage::PATH, SendDirect::PATH],
+                    config.rate_limits.send,
+                    "Sending too fast",
+                )
+                .trust_forwarded_for(config.rate_limits.trust_forwarded_for),
+        )
+        .layer(MetricsLayer::new(metrics))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span))
         .with_state(leptos_options);
 
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("listening on http://{}", &addr);
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    tracing::info!("listening on http://{}", &addr);
+    let service = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    let serve = axum::serve(listener, service)
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move { shutdown.started().await }
+        })
+        .into_future();
+    tokio::pin!(serve);
+
+    tokio::select! {
+        result = &mut serve => result.unwrap(),
+        () = shutdown::signal() => {
+            tracing::info!("shutting down, draining requests for up to {:?}", config.drain_timeout);
+            // Ends the event streams and stops accepting connections.
+            shutdown.begin();
+            match tokio::time::timeout(config.drain_timeout, &mut serve).await {
+                Ok(result) => result.unwrap(),
+                Err(_) => tracing::warn!("requests still in flight after the drain timeout, dropping them"),
+            }
+        }
+    }
 }
 
 #[cfg(not(feature = "ssr"))]
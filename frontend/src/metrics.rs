@@ -0,0 +1,141 @@
+//! Prometheus metrics for the server, served as text on `/metrics`.
+//!
+//! [`MetricsLayer`] wraps the router and times every request by the route it
+//! matched, so each server function gets its own latency histogram. Login
+//! failures are counted by the `join` server function through
+//! [`join_failed`].
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::extract::MatchedPath;
+use leptos::prelude::*;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use tower::{Layer, Service};
+
+pub struct Metrics {
+    registry: Registry,
+    /// From a request arriving to its response headers, by method, route and
+    /// status.
+    pub requests: HistogramVec,
+    /// Logins refused, by reason.
+    pub join_failures: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        let registry = Registry::new();
+        let requests = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from a request arriving to its response starting, by method, route and status.",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+        let join_failures = IntCounterVec::new(
+            Opts::new("chat_join_failures_total", "Logins refused, by reason."),
+            &["reason"],
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(requests.clone()))
+            .expect("metric names are unique");
+        registry
+            .register(Box::new(join_failures.clone()))
+            .expect("metric names are unique");
+        Arc::new(Self {
+            registry,
+            requests,
+            join_failures,
+        })
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut text = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut text)
+            .expect("metrics encode");
+        String::from_utf8(text).expect("metrics are UTF-8")
+    }
+}
+
+/// Counts a refused login in the [`Metrics`] of the current request.
+pub fn join_failed(reason: &str) {
+    expect_context::<Arc<Metrics>>()
+        .join_failures
+        .with_label_values(&[reason])
+        .inc();
+}
+
+/// Records requests in [`Metrics::requests`]. Requests no route matched, like
+/// those for static files, are recorded under the route `fallback`.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let started = Instant::now();
+        let method = request.method().to_string();
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or("fallback", |path| path.as_str())
+            .to_string();
+        let metrics = Arc::clone(&self.metrics);
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            metrics
+                .requests
+                .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+                .observe(started.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
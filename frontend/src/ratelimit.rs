@@ -0,0 +1,141 @@
+//! Rate limiting of the server functions, keyed by client IP.
+//!
+//! Every client gets a token bucket per limit. Requests that find theirs
+//! empty get `429 Too Many Requests` with a `Retry-After` header. The body is
+//! a server function error, so the page shows the message like any other
+//! failure.
+//!
+//! Behind a reverse proxy every request comes from the proxy's address, so
+//! all clients would share one bucket. [`RateLimitLayer::trust_forwarded_for`]
+//! keys them by the address the proxy puts in `X-Forwarded-For` instead.
+
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::response::{IntoResponse, Response};
+use chat_common::ratelimit::{refusal_message, retry_after};
+use http::StatusCode;
+use leptos::prelude::*;
+use leptos::server_fn::error::FromServerFnError;
+use tower::{Layer, Service};
+
+pub use chat_common::ratelimit::{Quota, RateLimiter};
+
+struct Limit {
+    paths: Vec<&'static str>,
+    limiter: RateLimiter<IpAddr>,
+    /// Start of the message refused requests get, e.g. "Sending too fast".
+    refusal: &'static str,
+}
+
+/// Limits requests to the paths given to [`RateLimitLayer::limit`], keyed by
+/// the client address from [`ConnectInfo`]. Requests without one pass.
+#[derive(Clone, Default)]
+pub struct RateLimitLayer {
+    limits: Vec<Arc<Limit>>,
+    trust_forwarded_for: bool,
+}
+
+impl RateLimitLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a limit shared by requests to any of `paths`.
+    pub fn limit(mut self, paths: &[&'static str], quota: Quota, refusal: &'static str) -> Self {
+        self.limits.push(Arc::new(Limit {
+            paths: paths.to_vec(),
+            limiter: RateLimiter::new(quota),
+            refusal,
+        }));
+        self
+    }
+
+    /// Keys requests by the last address in their `X-Forwarded-For` header,
+    /// the one added by the proxy in front, when `trust` is set. Only safe
+    /// when every request comes through that proxy, since clients can send
+    /// the header themselves.
+    pub fn trust_forwarded_for(mut self, trust: bool) -> Self {
+        self.trust_forwarded_for = trust;
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limits: self.limits.clone(),
+            trust_forwarded_for: self.trust_forwarded_for,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limits: Vec<Arc<Limit>>,
+    trust_forwarded_for: bool,
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| forwarded_for(request.headers()))
+            .flatten();
+        let client = forwarded.or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        });
+        let path = request.uri().path();
+        let limit = self.limits.iter().find(|limit| limit.paths.contains(&path));
+        if let (Some(client), Some(limit)) = (client, limit) {
+            if let Err(wait) = limit.limiter.check(client) {
+                let response = refusal(limit.refusal, wait);
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+/// The last address in the `X-Forwarded-For` headers, if it parses.
+fn forwarded_for(headers: &http::HeaderMap) -> Option<IpAddr> {
+    let value = headers.get_all("x-forwarded-for").iter().next_back()?;
+    value.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+}
+
+fn refusal(what: &str, wait: Duration) -> Response {
+    let seconds = retry_after(wait);
+    let error = ServerFnError::new(refusal_message(what, seconds));
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(http::header::RETRY_AFTER, seconds.to_string())],
+        error.ser(),
+    )
+        .into_response()
+}
@@ -0,0 +1,60 @@
+//! Server-side handling of the session token minted by the backend's `Join`.
+//!
+//! The token lives in an HttpOnly cookie so browser code never sees it; server
+//! functions read it back and forward it to the backend as gRPC metadata.
+
+use http::header::{COOKIE, SET_COOKIE};
+use http::{HeaderMap, HeaderValue};
+use leptos::prelude::*;
+use leptos_axum::ResponseOptions;
+
+const COOKIE_NAME: &str = "chat_session";
+
+fn set_cookie(value: &str) {
+    let response = expect_context::<ResponseOptions>();
+    if let Ok(value) = HeaderValue::from_str(value) {
+        response.insert_header(SET_COOKIE, value);
+    }
+}
+
+/// Stores `token` in the session cookie of the current response.
+pub fn store_token(token: &str) {
+    set_cookie(&format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/",
+        COOKIE_NAME, token
+    ));
+}
+
+/// Tells the browser to drop the session cookie.
+pub fn clear_token() {
+    set_cookie(&format!(
+        "{}=; HttpOnly; SameSite=Strict; Path=/; Max-Age=0",
+        COOKIE_NAME
+    ));
+}
+
+/// Reads the session token from the incoming request's cookies.
+pub async fn token() -> Option<String> {
+    let headers: HeaderMap = leptos_axum::extract().await.ok()?;
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, token)| token.to_string())
+}
+
+/// Wraps `message` in a gRPC request that carries the caller's session, if
+/// they have one. The backend decides whether the call needs it.
+pub async fn request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    if let Some(value) = token()
+        .await
+        .and_then(|token| format!("Bearer {}", token).parse().ok())
+    {
+        request.metadata_mut().insert("authorization", value);
+    }
+    request
+}
@@ -0,0 +1,15 @@
+//! Graceful shutdown for the server.
+//!
+//! The event streams of `handle_messages` last as long as the page is open,
+//! so they are wrapped with [`Shutdown::wrap`] to end when the server stops.
+//! The page then reconnects, to another instance or to this one once it
+//! restarts.
+
+use leptos::prelude::*;
+
+pub use chat_common::shutdown::{signal, Shutdown};
+
+/// The shutdown state provided as context to every request.
+pub fn current() -> Shutdown {
+    expect_context::<Shutdown>()
+}
@@ -0,0 +1,56 @@
+//! Logging and distributed tracing for the server, set up by
+//! [`chat_common::telemetry`].
+//!
+//! Each HTTP request is handled in a [`request_span`], and calls to the
+//! backend carry that span's context as W3C `traceparent` metadata (see
+//! [`inject`]), so the backend's spans land in the same trace.
+
+use opentelemetry::global;
+use opentelemetry::propagation::Injector;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub use chat_common::telemetry::{Telemetry, TelemetryError};
+
+/// Name the frontend's spans are exported under.
+const SERVICE_NAME: &str = "chat-frontend";
+
+/// Sets up logging, and exporting the frontend's spans, see
+/// [`chat_common::telemetry::init`].
+pub fn init(log_level: &str, otlp_endpoint: Option<&str>) -> Result<Telemetry, TelemetryError> {
+    chat_common::telemetry::init(SERVICE_NAME, log_level, otlp_endpoint)
+}
+
+/// The span an HTTP request is handled in, including the server functions
+/// it calls.
+pub fn request_span<B>(request: &http::Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "http",
+        otel.name = %format!("{} {}", request.method(), request.uri().path()),
+        otel.kind = "server",
+    );
+    chat_common::telemetry::join_trace(&span, request.headers());
+    span
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Adds the current span's trace context to the metadata of a gRPC request.
+pub fn inject(metadata: &mut MetadataMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
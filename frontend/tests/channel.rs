@@ -0,0 +1,159 @@
+//! The shared channel to the backend across backend restarts. A health
+//! service stands in for the backend, since only the connection matters.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use frontend::backend::Backend;
+use frontend::config::Args;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tonic::Code;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+
+use common::{free_addr, http_args};
+
+/// A server on `addr` until stopped.
+struct Running {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Running {
+    async fn start(addr: SocketAddr) -> Self {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let incoming = TcpListenerStream::new(listener).map(move |socket| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            socket
+        });
+
+        let (_, health) = tonic_health::server::health_reporter();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            Server::builder()
+                .add_service(health)
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = stopped.await;
+                })
+                .await
+                .unwrap();
+        });
+        Self {
+            stop,
+            task,
+            connections,
+        }
+    }
+
+    /// Connections accepted so far.
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Closes the listener and every connection to it.
+    async fn stop(self) {
+        let _ = self.stop.send(());
+        self.task.await.unwrap();
+    }
+}
+
+fn backend(addr: SocketAddr, request_timeout: u64) -> Backend {
+    common::backend(Args {
+        request_timeout: Some(request_timeout),
+        ..http_args(addr)
+    })
+}
+
+async fn check(backend: &Backend) -> Result<(), tonic::Status> {
+    HealthClient::new(backend.channel())
+        .check(HealthCheckRequest::default())
+        .await
+        .map(drop)
+}
+
+#[tokio::test]
+async fn connects_on_first_call() {
+    let addr = free_addr();
+    // Nothing is listening yet, which is fine until a call is made.
+    let backend = backend(addr, 5);
+
+    let server = Running::start(addr).await;
+    check(&backend).await.unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reconnects_after_backend_restart() {
+    let addr = free_addr();
+    let backend = backend(addr, 5);
+
+    let server = Running::start(addr).await;
+    check(&backend).await.unwrap();
+    server.stop().await;
+
+    let status = check(&backend).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unavailable, "{}", status);
+
+    // The same channel, not a new one, picks the backend up again.
+    let server = Running::start(addr).await;
+    check(&backend).await.unwrap();
+    check(&backend).await.unwrap();
+    assert_eq!(server.connections(), 1);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn calls_share_one_connection() {
+    let addr = free_addr();
+    let backend = backend(addr, 5);
+    let server = Running::start(addr).await;
+
+    let calls = (0..20).map(|_| {
+        let backend = backend.clone();
+        tokio::spawn(async move { check(&backend).await })
+    });
+    for call in calls {
+        call.await.unwrap().unwrap();
+    }
+    assert_eq!(server.connections(), 1);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn request_timeout_bounds_unresponsive_backend() {
+    // Accepts connections but never answers.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hold = tokio::spawn(async move {
+        let mut sockets = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    let backend = backend(addr, 1);
+    let started = Instant::now();
+    let status = check(&backend).await.unwrap_err();
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        started.elapsed()
+    );
+    assert!(
+        matches!(status.code(), Code::Cancelled | Code::Unavailable),
+        "{}",
+        status
+    );
+    hold.abort();
+}
@@ -0,0 +1,26 @@
+//! Clients to a backend that the tests serve themselves.
+
+#![allow(dead_code, unused_imports)]
+
+use std::net::SocketAddr;
+
+use frontend::backend::Backend;
+use frontend::config::{Args, Config};
+
+pub use chat_common::testing::free_addr;
+
+/// Flags for reaching a backend on `addr` over plain HTTP/2, giving up on
+/// connecting after a second.
+pub fn http_args(addr: SocketAddr) -> Args {
+    Args {
+        backend: Some(format!("http://{}", addr)),
+        connect_timeout: Some(1),
+        ..Default::default()
+    }
+}
+
+/// The client the frontend would use when started with `args`.
+pub fn backend(args: Args) -> Backend {
+    let config = Config::from_layers(args, Args::default()).unwrap();
+    Backend::new(config.endpoint().unwrap())
+}
@@ -0,0 +1,108 @@
+//! Layering of the frontend's settings and the errors for invalid ones.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chat_common::config::ConfigError as SettingsError;
+use clap::Parser;
+use frontend::config::{Args, Config, ConfigError};
+
+#[test]
+fn flags_beat_env_beat_file_beat_defaults() {
+    // Only this test reads the environment, the others build `Args` directly.
+    std::env::set_var("CHAT_FRONTEND_CONNECT_TIMEOUT", "2");
+    std::env::set_var("CHAT_FRONTEND_REQUEST_TIMEOUT", "3");
+    let args = Args::try_parse_from(["frontend", "--connect-timeout", "1"]).unwrap();
+    let file: Args = toml::from_str(
+        "connect_timeout = 4
+         request_timeout = 5
+         keepalive_interval = 6",
+    )
+    .unwrap();
+
+    let channel = Config::from_layers(args, file).unwrap().channel;
+    assert_eq!(channel.connect_timeout, Duration::from_secs(1));
+    assert_eq!(channel.request_timeout, Duration::from_secs(3));
+    assert_eq!(channel.keepalive_interval, Duration::from_secs(6));
+    assert_eq!(channel.keepalive_timeout, Duration::from_secs(10));
+}
+
+#[test]
+fn defaults() {
+    let config = Config::from_layers(Args::default(), Args::default()).unwrap();
+    assert_eq!(config.backend, "http://[::1]:50051");
+    assert!(config.listen.is_none());
+    assert!(config.tls.is_none());
+    let join = config.rate_limits.join;
+    assert_eq!((join.rate, join.burst), (0.2, 5));
+    let send = config.rate_limits.send;
+    assert_eq!((send.rate, send.burst), (2.0, 10));
+    assert!(!config.rate_limits.trust_forwarded_for);
+}
+
+#[test]
+fn tls_identity_needs_both_cert_and_key() {
+    let key_only = Args {
+        tls_key: Some(PathBuf::from("frontend.key")),
+        ..Default::default()
+    };
+    let result = Config::from_layers(key_only, Args::default());
+    assert!(matches!(
+        result,
+        Err(ConfigError::Settings(SettingsError::Incomplete(
+            "tls_key", "tls_cert"
+        )))
+    ));
+
+    // A CA alone is enough to verify the backend.
+    let ca_only = Args {
+        tls_ca: Some(PathBuf::from("ca.pem")),
+        ..Default::default()
+    };
+    let tls = Config::from_layers(ca_only, Args::default())
+        .unwrap()
+        .tls
+        .unwrap();
+    assert!(tls.identity.is_none());
+}
+
+#[test]
+fn rate_limits_must_be_positive() {
+    for (args, setting) in [
+        (
+            Args {
+                join_rate: Some(-1.0),
+                ..Default::default()
+            },
+            "join_rate",
+        ),
+        (
+            Args {
+                join_burst: Some(0),
+                ..Default::default()
+            },
+            "join_burst",
+        ),
+        (
+            Args {
+                send_rate: Some(f64::NAN),
+                ..Default::default()
+            },
+            "send_rate",
+        ),
+        (
+            Args {
+                send_burst: Some(0),
+                ..Default::default()
+            },
+            "send_burst",
+        ),
+    ] {
+        match Config::from_layers(args, Args::default()) {
+            Err(ConfigError::Settings(SettingsError::NotPositive(name))) => {
+                assert_eq!(name, setting)
+            }
+            other => panic!("expected {} to be refused, got {:?}", setting, other),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use chat_proto::chat_event::Event;
+use chat_proto::{ChatEvent, ChatMessage};
+use frontend::feed::{Feed, Row};
+
+fn message(id: u64, parent_id: u64) -> ChatMessage {
+    ChatMessage {
+        id,
+        parent_id,
+        from: String::from("alice"),
+        msg: format!("message {}", id),
+        ..Default::default()
+    }
+}
+
+fn event(seq: u64, event: Event) -> ChatEvent {
+    ChatEvent {
+        seq,
+        event: Some(event),
+    }
+}
+
+#[test]
+fn changes_only_touch_their_own_row() {
+    let mut feed = Feed::new((1..=5).map(|id| message(id, 0)));
+    let before = feed.rows(|_| true);
+
+    let mut edited = message(3, 0);
+    edited.msg = String::from("edited");
+    edited.revision = 6;
+    feed.apply(event(6, Event::Edited(edited)));
+    feed.apply(event(7, Event::NewMessage(message(7, 0))));
+
+    let after = feed.rows(|_| true);
+    assert_eq!(after.len(), 6);
+    for (old, new) in before.iter().zip(&after) {
+        assert_eq!(old == new, old.id != 3, "row {}", old.id);
+    }
+    assert_eq!(feed.get(3).unwrap().msg, "edited");
+}
+
+#[test]
+fn replayed_and_unknown_messages() {
+    let mut feed = Feed::new([message(5, 0)]);
+
+    // Changes to messages that scrolled out are dropped.
+    feed.apply(event(6, Event::Deleted(message(1, 0))));
+    assert_eq!(feed.len(), 1);
+
+    // A replay after reconnecting replaces the loaded copy.
+    let mut replayed = message(5, 0);
+    replayed.revision = 8;
+    feed.apply(event(5, Event::NewMessage(replayed)));
+    assert_eq!(feed.len(), 1);
+    assert_eq!(feed.last_seq(), 8);
+}
+
+#[test]
+fn reply_counts_follow_deletes_and_trims() {
+    let mut feed = Feed::new([message(1, 0), message(2, 1), message(3, 1), message(4, 0)]);
+    assert_eq!(feed.replies(1), 2);
+
+    let mut deleted = message(2, 1);
+    deleted.deleted = true;
+    deleted.revision = 5;
+    feed.apply(event(5, Event::Deleted(deleted)));
+    assert_eq!(feed.replies(1), 1);
+
+    feed.trim_oldest(1);
+    assert_eq!(feed.oldest_id(), Some(4));
+    assert_eq!(feed.replies(1), 0);
+}
+
+#[test]
+fn trims_whole_threads_from_either_end() {
+    let mut feed = Feed::new([
+        message(1, 0),
+        message(2, 0),
+        message(3, 1),
+        message(4, 0),
+        message(5, 2),
+    ]);
+    assert_eq!(feed.top_level(), 3);
+
+    // Replies don't count against the cap, and go with their parent.
+    feed.trim_newest(2);
+    assert_eq!((feed.oldest_id(), feed.newest_id()), (Some(1), Some(2)));
+    assert_eq!(feed.len(), 4);
+    assert_eq!(feed.replies(2), 1);
+
+    feed.trim_oldest(1);
+    assert_eq!((feed.oldest_id(), feed.newest_id()), (Some(2), Some(2)));
+    assert_eq!(feed.len(), 2);
+    assert_eq!(feed.replies(1), 0);
+
+    // Replies to messages that aren't loaded aren't kept either.
+    feed.apply(event(6, Event::NewMessage(message(6, 1))));
+    feed.apply(event(7, Event::NewMessage(message(7, 2))));
+    assert_eq!(feed.len(), 3);
+    assert_eq!(feed.replies(2), 2);
+}
+
+#[test]
+fn counted_feed_keeps_only_reply_counts() {
+    let counts = HashMap::from([(1, 2), (9, 4)]);
+    let mut feed = Feed::counted([message(1, 0), message(2, 0)], counts, 8);
+    // Counts for messages that aren't loaded are left out.
+    assert_eq!((feed.replies(1), feed.replies(9)), (2, 0));
+    assert_eq!(feed.last_seq(), 8);
+
+    feed.apply(event(10, Event::NewMessage(message(10, 2))));
+    feed.apply(event(11, Event::NewMessage(message(11, 5))));
+    let mut deleted = message(3, 1);
+    deleted.deleted = true;
+    deleted.revision = 12;
+    feed.apply(event(12, Event::Deleted(deleted)));
+    let mut edited = message(10, 2);
+    edited.edited = true;
+    edited.revision = 13;
+    feed.apply(event(13, Event::Edited(edited)));
+    assert_eq!(feed.len(), 2);
+    assert_eq!((feed.replies(1), feed.replies(2)), (1, 1));
+
+    feed.extend_counted([message(20, 0)], HashMap::from([(20, 3)]));
+    assert_eq!(feed.replies(20), 3);
+    feed.trim_oldest(1);
+    assert_eq!(
+        (feed.replies(1), feed.replies(2), feed.replies(20)),
+        (0, 0, 3)
+    );
+}
+
+/// A long busy session as the chat window keeps it while following the
+/// newest messages: every event renders at most the one row it is about,
+/// however long the session runs.
+#[test]
+fn long_session_renders_one_row_per_event() {
+    const EVENTS: u64 = 10_000;
+    const MAX_LOADED: usize = 300;
+
+    let mut feed = Feed::counted([], HashMap::new(), 0);
+    let mut rendered = HashSet::new();
+    for seq in 1..=EVENTS {
+        let change = if seq % 10 == 0 {
+            // A reaction to the newest message.
+            let mut reacted = feed.get(feed.newest_id().unwrap()).cloned().unwrap();
+            reacted.revision = seq;
+            Event::Reactions(reacted)
+        } else if seq % 7 == 0 {
+            // A reply, which only updates its parent's row.
+            Event::NewMessage(message(seq, seq - 1))
+        } else {
+            Event::NewMessage(message(seq, 0))
+        };
+        feed.apply(event(seq, change));
+        feed.trim_oldest(MAX_LOADED);
+
+        let rows: HashSet<Row> = feed.rows(|msg| msg.parent_id == 0).into_iter().collect();
+        let changed = rows.difference(&rendered).count();
+        assert!(changed <= 1, "event {} rendered {} rows", seq, changed);
+        rendered = rows;
+    }
+
+    assert_eq!(feed.len(), MAX_LOADED);
+    assert_eq!(rendered.len(), MAX_LOADED);
+}
@@ -0,0 +1,171 @@
+use chat_proto::chat_event::Event;
+use chat_proto::{ChatEvent, ChatMessage};
+use frontend::framing::{self, FrameDecoder, FrameError, MAX_FRAME_LEN};
+use futures::executor::block_on;
+use futures::StreamExt;
+
+fn events() -> Vec<ChatEvent> {
+    // Sizes on both sides of the one and two byte length prefixes.
+    [0, 1, 50, 126, 127, 128, 300, 20_000]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, len)| ChatEvent {
+            seq: idx as u64 + 1,
+            event: Some(Event::NewMessage(ChatMessage {
+                id: idx as u64 + 1,
+                msg: "x".repeat(len),
+                ..Default::default()
+            })),
+        })
+        .collect()
+}
+
+fn encoded(events: &[ChatEvent]) -> Vec<u8> {
+    events.iter().flat_map(framing::encode).collect()
+}
+
+/// Feeds `chunks` to a decoder one at a time, collecting every frame.
+fn decode_chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Vec<ChatEvent> {
+    let mut decoder = FrameDecoder::new();
+    let mut decoded = vec![];
+    for chunk in chunks {
+        decoder.push(chunk);
+        while let Some(event) = decoder.decode::<ChatEvent>() {
+            decoded.push(event.expect("valid frame"));
+        }
+    }
+    assert_eq!(decoder.buffered(), 0);
+    decoded
+}
+
+/// Deterministic pseudo-random chunk sizes between 1 and `max`.
+fn chunk_sizes(seed: u64, max: u64) -> impl Iterator<Item = usize> {
+    let mut state = seed;
+    std::iter::repeat_with(move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((state >> 33) % max + 1) as usize
+    })
+}
+
+fn split(bytes: &[u8], sizes: impl Iterator<Item = usize>) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut rest = bytes;
+    for size in sizes {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(size.min(rest.len()));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+#[test]
+fn all_frames_in_one_chunk() {
+    let events = events();
+    assert_eq!(decode_chunks([&encoded(&events)[..]]), events);
+}
+
+#[test]
+fn one_byte_at_a_time() {
+    let events = events();
+    let bytes = encoded(&events);
+    assert_eq!(decode_chunks(bytes.chunks(1)), events);
+}
+
+#[test]
+fn split_at_every_offset() {
+    let events = events();
+    let bytes = encoded(&events);
+    for at in 0..=bytes.len() {
+        let (head, tail) = bytes.split_at(at);
+        assert_eq!(decode_chunks([head, tail]), events, "split at {}", at);
+    }
+}
+
+#[test]
+fn random_splits_and_merges() {
+    let events = events();
+    let bytes = encoded(&events);
+    for seed in 0..200 {
+        // Small chunks split frames, large ones merge several.
+        let max = if seed % 2 == 0 { 8 } else { 30_000 };
+        let chunks = split(&bytes, chunk_sizes(seed, max));
+        assert_eq!(decode_chunks(chunks), events, "seed {}", seed);
+    }
+}
+
+#[test]
+fn empty_chunks_are_ignored() {
+    let events = events();
+    let bytes = encoded(&events);
+    let chunks = bytes.chunks(7).flat_map(|chunk| [&[][..], chunk]);
+    assert_eq!(decode_chunks(chunks), events);
+}
+
+#[test]
+fn invalid_frame_is_skipped() {
+    let events = events();
+    let mut bytes = encoded(&events[..1]);
+    // A whole frame holding an invalid message: field 1 with wire type 7.
+    bytes.extend_from_slice(&[1, 0x0f]);
+    bytes.extend(encoded(&events[1..]));
+
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&bytes);
+    let results: Vec<_> = std::iter::from_fn(|| decoder.decode::<ChatEvent>()).collect();
+    assert_eq!(results.len(), events.len() + 1);
+    assert!(matches!(results[1], Err(FrameError::Decode(_))));
+    let decoded: Vec<_> = results.into_iter().filter_map(Result::ok).collect();
+    assert_eq!(decoded, events);
+}
+
+#[test]
+fn oversized_length_is_rejected() {
+    let mut decoder = FrameDecoder::new();
+    let mut len = vec![];
+    prost::encoding::encode_varint(MAX_FRAME_LEN as u64 + 1, &mut len);
+    decoder.push(&len);
+    assert!(matches!(
+        decoder.decode::<ChatEvent>(),
+        Some(Err(FrameError::InvalidLength))
+    ));
+
+    // More than ten continuation bytes can't be a length either.
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&[0xff; 11]);
+    assert!(matches!(
+        decoder.decode::<ChatEvent>(),
+        Some(Err(FrameError::InvalidLength))
+    ));
+}
+
+#[test]
+fn stream_of_chunks() {
+    let events = events();
+    let bytes = encoded(&events);
+    let chunks: Vec<Result<Vec<u8>, ()>> = split(&bytes, chunk_sizes(7, 100))
+        .into_iter()
+        .map(|chunk| Ok(chunk.to_vec()))
+        .collect();
+    let decoded: Vec<ChatEvent> = block_on(
+        framing::decode_stream(futures::stream::iter(chunks))
+            .map(|event| event.expect("valid frame"))
+            .collect(),
+    );
+    assert_eq!(decoded, events);
+}
+
+#[test]
+fn stream_ends_after_invalid_length() {
+    let mut bytes = encoded(&events()[..2]);
+    bytes.extend_from_slice(&[0xff; 11]);
+    bytes.extend(encoded(&events()));
+    let chunks = futures::stream::iter([Ok::<_, ()>(bytes)]);
+    let results: Vec<_> = block_on(framing::decode_stream::<ChatEvent, _, _, _>(chunks).collect());
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[2], Err(FrameError::InvalidLength)));
+}
@@ -0,0 +1,49 @@
+//! Readiness of the frontend, as reported by the backend's health service.
+
+mod common;
+
+use std::net::SocketAddr;
+
+use chat_proto::chat_service_server::SERVICE_NAME;
+use frontend::health;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use common::{backend, free_addr, http_args};
+
+/// Serves only the health service, reporting the chat service as `status`.
+async fn health_server(status: ServingStatus) -> (SocketAddr, HealthReporter) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (reporter, service) = tonic_health::server::health_reporter();
+    reporter.set_service_status(SERVICE_NAME, status).await;
+    tokio::spawn(
+        Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (addr, reporter)
+}
+
+#[tokio::test]
+async fn ready_while_backend_serves() {
+    let (addr, reporter) = health_server(ServingStatus::Serving).await;
+    let backend = backend(http_args(addr));
+    health::check(&backend).await.unwrap();
+
+    reporter
+        .set_service_status(SERVICE_NAME, ServingStatus::NotServing)
+        .await;
+    let reason = health::check(&backend).await.unwrap_err();
+    assert_eq!(reason, "backend is NOT_SERVING");
+}
+
+#[tokio::test]
+async fn not_ready_without_backend() {
+    let reason = health::check(&backend(http_args(free_addr())))
+        .await
+        .unwrap_err();
+    assert!(reason.starts_with("backend unreachable"), "{}", reason);
+}
@@ -0,0 +1,53 @@
+//! Request latencies recorded by the metrics layer around the router.
+
+use axum::body::Body;
+use axum::routing::post;
+use axum::Router;
+use frontend::metrics::{Metrics, MetricsLayer};
+use tower::ServiceExt;
+
+fn router(metrics: &std::sync::Arc<Metrics>) -> Router {
+    Router::new()
+        .route("/api/list_rooms", post(|| async { "[]" }))
+        .fallback(|| async { (http::StatusCode::NOT_FOUND, "not found") })
+        .layer(MetricsLayer::new(metrics.clone()))
+}
+
+async fn request(router: Router, method: &str, path: &str) -> http::StatusCode {
+    let request = http::Request::builder()
+        .method(method)
+        .uri(path)
+        .body(Body::empty())
+        .unwrap();
+    router.oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn records_requests_by_route() {
+    let metrics = Metrics::new();
+    let router = router(&metrics);
+
+    assert_eq!(
+        request(router.clone(), "POST", "/api/list_rooms").await,
+        200
+    );
+    assert_eq!(
+        request(router.clone(), "POST", "/api/list_rooms").await,
+        200
+    );
+    assert_eq!(request(router, "GET", "/pkg/missing.js").await, 404);
+
+    let requests = |labels: &[&str]| {
+        metrics
+            .requests
+            .with_label_values(labels)
+            .get_sample_count()
+    };
+    assert_eq!(requests(&["POST", "/api/list_rooms", "200"]), 2);
+    assert_eq!(requests(&["GET", "fallback", "404"]), 1);
+
+    let text = metrics.encode();
+    assert!(text.contains(
+        r#"http_request_duration_seconds_count{method="POST",route="/api/list_rooms",status="200"} 2"#
+    ));
+}
@@ -0,0 +1,122 @@
+//! Server function requests refused by the rate limit layer around the router.
+
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::routing::post;
+use axum::Router;
+use frontend::ratelimit::{Quota, RateLimitLayer};
+use leptos::prelude::*;
+use leptos::server_fn::error::FromServerFnError;
+use tower::ServiceExt;
+
+fn router() -> Router {
+    Router::new()
+        .route("/api/join", post(|| async { "true" }))
+        .route("/api/list_rooms", post(|| async { "[]" }))
+        .layer(RateLimitLayer::new().limit(
+            &["/api/join"],
+            Quota {
+                rate: 1.0,
+                burst: 2,
+            },
+            "Too many login attempts",
+        ))
+}
+
+fn request(path: &str, client: &str) -> http::Request<Body> {
+    let mut request = http::Request::builder()
+        .method("POST")
+        .uri(path)
+        .body(Body::empty())
+        .unwrap();
+    let addr: SocketAddr = client.parse().unwrap();
+    request.extensions_mut().insert(ConnectInfo(addr));
+    request
+}
+
+#[tokio::test]
+async fn refuses_requests_beyond_burst() {
+    let router = router();
+    for _ in 0..2 {
+        let response = router
+            .clone()
+            .oneshot(request("/api/join", "10.0.0.1:5000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    let response = router
+        .clone()
+        .oneshot(request("/api/join", "10.0.0.1:5001"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()[http::header::RETRY_AFTER], "1");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let error: ServerFnError = ServerFnError::de(body);
+    assert_eq!(
+        error,
+        ServerFnError::ServerError("Too many login attempts, try again in 1 second.".into())
+    );
+
+    let other_client = router
+        .clone()
+        .oneshot(request("/api/join", "10.0.0.2:5000"))
+        .await
+        .unwrap();
+    assert_eq!(other_client.status(), 200);
+    let other_path = router
+        .oneshot(request("/api/list_rooms", "10.0.0.1:5000"))
+        .await
+        .unwrap();
+    assert_eq!(other_path.status(), 200);
+}
+
+#[tokio::test]
+async fn keys_on_forwarded_for_only_when_trusted() {
+    let forwarded = |client: &str| {
+        let mut request = request("/api/join", "10.0.0.1:5000");
+        request.headers_mut().insert(
+            "x-forwarded-for",
+            format!("192.0.2.1, {}", client).parse().unwrap(),
+        );
+        request
+    };
+    let layer = RateLimitLayer::new().limit(
+        &["/api/join"],
+        Quota {
+            rate: 1.0,
+            burst: 1,
+        },
+        "Too many login attempts",
+    );
+
+    // Behind a trusted proxy, every client appended by it has a bucket.
+    let trusted = Router::new()
+        .route("/api/join", post(|| async { "true" }))
+        .layer(layer.clone().trust_forwarded_for(true));
+    for client in ["10.0.0.2", "10.0.0.3"] {
+        let response = trusted.clone().oneshot(forwarded(client)).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    let response = trusted.oneshot(forwarded("10.0.0.2")).await.unwrap();
+    assert_eq!(response.status(), 429);
+
+    // Otherwise the header is ignored, and they share the proxy's.
+    let untrusted = Router::new()
+        .route("/api/join", post(|| async { "true" }))
+        .layer(layer);
+    let response = untrusted
+        .clone()
+        .oneshot(forwarded("10.0.0.2"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = untrusted.oneshot(forwarded("10.0.0.3")).await.unwrap();
+    assert_eq!(response.status(), 429);
+}
@@ -0,0 +1,104 @@
+//! Trace context carried from the frontend's spans into calls to the backend.
+
+use std::sync::{Arc, Mutex};
+
+use chat_proto::Empty;
+use frontend::backend::Backend;
+use frontend::telemetry;
+use opentelemetry::global;
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Endpoint, Server};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Sets up tracing with OpenTelemetry for the current thread, without
+/// exporting anything.
+fn subscriber() -> tracing::subscriber::DefaultGuard {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = SdkTracerProvider::builder().build().tracer("test");
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_default(subscriber)
+}
+
+fn trace_id(span: &tracing::Span) -> String {
+    span.context().span().span_context().trace_id().to_string()
+}
+
+#[test]
+fn request_span_joins_incoming_trace() {
+    let _guard = subscriber();
+    let request = http::Request::builder()
+        .uri("/api/list_rooms")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+        )
+        .body(())
+        .unwrap();
+
+    assert_eq!(trace_id(&telemetry::request_span(&request)), TRACE_ID);
+}
+
+#[test]
+fn request_span_without_trace_context_starts_one() {
+    let _guard = subscriber();
+    let request = http::Request::builder()
+        .uri("/api/list_rooms")
+        .body(())
+        .unwrap();
+
+    let span = telemetry::request_span(&request);
+    assert_ne!(trace_id(&span), "00000000000000000000000000000000");
+}
+
+#[tokio::test]
+async fn backend_calls_carry_the_current_trace() {
+    let _guard = subscriber();
+
+    // Records the traceparent of every request it gets. It has no chat
+    // service, so the calls themselves fail.
+    let seen = Arc::new(Mutex::new(vec![]));
+    let record = {
+        let seen = seen.clone();
+        tower::util::MapRequestLayer::new(move |request: http::Request<_>| {
+            let traceparent = request
+                .headers()
+                .get("traceparent")
+                .map(|value| value.to_str().unwrap().to_string());
+            seen.lock().unwrap().push(traceparent);
+            request
+        })
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (_, health) = tonic_health::server::health_reporter();
+    tokio::spawn(
+        Server::builder()
+            .layer(record)
+            .add_service(health)
+            .serve_with_incoming(TcpIncoming::from(listener)),
+    );
+
+    let endpoint = Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+    let backend = Backend::new(endpoint);
+    let span = tracing::info_span!("server_fn");
+    let _ = backend
+        .client()
+        .list_rooms(Empty {})
+        .instrument(span.clone())
+        .await;
+
+    let seen = seen.lock().unwrap().clone();
+    let traceparent = seen[0].as_deref().expect("traceparent is sent");
+    assert_eq!(
+        traceparent.split('-').nth(1),
+        Some(trace_id(&span).as_str())
+    );
+}
@@ -0,0 +1,147 @@
+//! The frontend's channel to a backend serving TLS and mutual TLS, with
+//! certificates generated for each test. A health service stands in for the
+//! backend, since only the connection matters.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use frontend::backend::Backend;
+use frontend::config::{Args, Config, ConfigError};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+
+use common::{backend, http_args};
+
+/// A certificate authority that signs the server and client certificates.
+struct Ca {
+    issuer: Issuer<'static, KeyPair>,
+    pem: String,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let pem = params.self_signed(&key).unwrap().pem();
+        Self {
+            issuer: Issuer::new(params, key),
+            pem,
+        }
+    }
+
+    /// Returns a certificate for `name` and its key, both PEM encoded.
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()])
+            .unwrap()
+            .signed_by(&key, &self.issuer)
+            .unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+}
+
+fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Serves the health service over TLS as `localhost`, requiring a
+/// certificate from `client_ca` when given.
+async fn tls_server(ca: &Ca, client_ca: Option<&Ca>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (cert, key) = ca.issue("localhost");
+    let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(client_ca) = client_ca {
+        tls = tls.client_ca_root(Certificate::from_pem(&client_ca.pem));
+    }
+    let (_, health) = tonic_health::server::health_reporter();
+    tokio::spawn(
+        Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(health)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    addr
+}
+
+/// Flags for reaching the server on `addr` over TLS, trusting `ca`.
+fn https_args(dir: &Path, addr: SocketAddr, ca: &Ca) -> Args {
+    Args {
+        backend: Some(format!("https://localhost:{}", addr.port())),
+        tls_ca: Some(write(dir, "ca.pem", &ca.pem)),
+        connect_timeout: Some(1),
+        ..Default::default()
+    }
+}
+
+async fn check(backend: &Backend) -> Result<(), tonic::Status> {
+    HealthClient::new(backend.channel())
+        .check(HealthCheckRequest::default())
+        .await
+        .map(drop)
+}
+
+#[tokio::test]
+async fn connects_over_tls() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = Ca::new();
+    let addr = tls_server(&ca, None).await;
+
+    check(&backend(https_args(dir.path(), addr, &ca)))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rejects_untrusted_server() {
+    let dir = tempfile::tempdir().unwrap();
+    let addr = tls_server(&Ca::new(), None).await;
+
+    // Trusting another CA than the one that issued the server certificate.
+    let args = https_args(dir.path(), addr, &Ca::new());
+    assert!(check(&backend(args)).await.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_sends_client_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = Ca::new();
+    let client_ca = Ca::new();
+    let addr = tls_server(&ca, Some(&client_ca)).await;
+
+    // No client certificate.
+    assert!(check(&backend(https_args(dir.path(), addr, &ca)))
+        .await
+        .is_err());
+
+    let (cert, key) = client_ca.issue("frontend");
+    let args = Args {
+        tls_cert: Some(write(dir.path(), "frontend.pem", &cert)),
+        tls_key: Some(write(dir.path(), "frontend.key", &key)),
+        ..https_args(dir.path(), addr, &ca)
+    };
+    check(&backend(args)).await.unwrap();
+}
+
+#[test]
+fn tls_settings_need_https() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = Args {
+        tls_ca: Some(write(dir.path(), "ca.pem", &Ca::new().pem)),
+        ..http_args("127.0.0.1:50051".parse().unwrap())
+    };
+    let config = Config::from_layers(args, Args::default()).unwrap();
+    assert!(matches!(
+        config.endpoint(),
+        Err(ConfigError::TlsWithoutHttps)
+    ));
+}